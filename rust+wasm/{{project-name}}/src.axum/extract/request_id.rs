@@ -0,0 +1,65 @@
+//! Request id Extractor.
+
+use crate::error::AppError;
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use std::fmt;
+
+/// Request id of the current request, as accepted from the caller or
+/// generated (see [request_id](crate::middleware::request_id)).
+///
+/// Rejects the request (with an [AppError]) if no request id was set, i.e.
+/// outside of the request id middleware.
+///
+/// # Extractor example
+///
+/// ```rust,no_run
+/// use axum::{routing::get, Router};
+/// use {{crate_name}}::extract::request_id::RequestId;
+///
+/// async fn handler(request_id: RequestId) -> String {
+///     format!("handled {request_id}")
+/// }
+///
+/// let app: Router = Router::new().route("/", get(handler));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Request id, as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<tower_http::request_id::RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .map(|id| RequestId(id.to_string()))
+            .ok_or_else(|| {
+                AppError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Some("Missing request id"),
+                )
+            })
+    }
+}
@@ -13,16 +13,18 @@ pub mod extract;
 pub mod headers;
 pub mod metrics;
 pub mod middleware;
+pub mod propagation;
 pub mod router;
 pub mod routes;
 pub mod settings;
 pub mod tracer;
 pub mod tracing_layers;
-{% if bench %}
+
 /// Test utilities.
 #[cfg(any(test, feature = "test_utils"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "test_utils")))]
 pub mod test_utils;
+{% if bench %}
 /// Add two integers together.
 pub fn add(a: i32, b: i32) -> i32 {
     a + b
@@ -1,11 +1,11 @@
 //! {{project-name}}
 
-use anyhow::Result;
-use axum::{extract::Extension, routing::get, Router};
+use anyhow::{anyhow, Result};
+use axum::{extract::Extension, Router};
 use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
-use headers::HeaderName;
-use http::header;
-use std::{future::ready, io, iter::once, net::SocketAddr, time::Duration};
+use opentelemetry::global;
+use opentelemetry_sdk::logs::LoggerProvider;
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::{net::TcpListener, signal};
@@ -14,38 +14,61 @@ use tower_http::{
     catch_panic::CatchPanicLayer, sensitive_headers::SetSensitiveHeadersLayer,
     timeout::TimeoutLayer, ServiceBuilderExt,
 };
-use tracing::info;
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     filter::{dynamic_filter_fn, filter_fn, LevelFilter},
     prelude::*,
-    EnvFilter,
 };
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 use {{crate_name}}::{
     docs::ApiDoc,
-    metrics::{process, prom::setup_metrics_recorder},
-    middleware::{self, request_ulid::MakeRequestUlid, runtime},
+    metrics::{
+        cardinality::{self, CardinalityGuard},
+        process,
+        prom::setup_metrics_recorder,
+    },
+    middleware::{
+        self, correlation,
+        debug_request::DebugRequests,
+        redact::{self, Redactor},
+        request_id::RequestIds,
+        request_ulid::MakeRequestUlid,
+        runtime,
+    },
     router,
-    routes::fallback::notfound_404,
-    settings::{Otel, Settings},
-    tracer::init_tracer,
+    routes::admin::AdminState,
+    settings::{AppEnvironment, Logging, Otel, Settings},
+    tracer::{init_logger_provider, init_tracer},
     tracing_layers::{
-        format_layer::LogFmtLayer,
+        format_layer::{LogFmtLayer, SpanFields},
+        log_file,
+        log_filter::LogFilter,
+        log_sampler::LogSampler,
         metrics_layer::{MetricsLayer, METRIC_META_PREFIX},
+        otel_log_layer::{self, OtelLogLayer},
         storage_layer::StorageLayer,
     },
 };
 
-/// Request identifier field.
-const REQUEST_ID: &str = "request_id";
+/// Heap allocation tracking, reported with process metrics.
+#[cfg(feature = "alloc-metrics")]
+#[global_allocator]
+static ALLOCATOR: {{crate_name}}::metrics::allocator::TrackingAllocator =
+    {{crate_name}}::metrics::allocator::TrackingAllocator::new(std::alloc::System);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let (stdout_writer, _stdout_guard) = tracing_appender::non_blocking(io::stdout());
-
     let settings = Settings::load()?;
-    setup_tracing(stdout_writer, settings.otel())?;
+    let debug_requests = DebugRequests::new(&settings.logging().debug_request)?;
+    let request_ids = RequestIds::new(&settings.server().request_id)?;
+    let (log_filter, _log_guards, logger_provider) = setup_tracing(
+        settings.logging(),
+        settings.otel(),
+        settings.environment(),
+        debug_requests.max_level(),
+    )?;
 
     info!(
         subject = "app_settings",
@@ -54,38 +77,73 @@ async fn main() -> Result<()> {
         settings,
     );
 
-    let env = settings.environment();
-    let recorder_handle = setup_metrics_recorder()?;
+    let redactor = Redactor::new(&settings.logging().redaction)?;
+    let mut sensitive_headers = redactor.sensitive_headers().to_vec();
+    sensitive_headers.push(debug_requests.header().clone());
+    redact::init(redactor).map_err(|_| anyhow!("redaction rules already initialized"))?;
 
-    let app_metrics = async {
-        let metrics_router = Router::new()
-            .route("/metrics", get(move || ready(recorder_handle.render())))
-            .fallback(notfound_404);
+    cardinality::init(CardinalityGuard::new(
+        settings.monitoring().max_label_values,
+    ))
+    .map_err(|_| anyhow!("metrics cardinality guard already initialized"))?;
 
-        let router = metrics_router.layer(CatchPanicLayer::custom(runtime::catch_panic));
+    let env = settings.environment();
+    let recorder_handle = setup_metrics_recorder(settings.otel(), settings.metrics(), env)?;
+
+    let app_admin = async {
+        let state = AdminState::new(
+            recorder_handle,
+            serde_json::to_value(&settings)?,
+            log_filter,
+        );
+        let router = router::setup_admin_router(state, settings.admin().auth.clone())
+            .layer(CatchPanicLayer::custom(runtime::catch_panic));
 
         // Spawn tick-driven process collection task
         tokio::task::spawn(process::collect_metrics(
             settings.monitoring().process_collector_interval,
+            settings.monitoring().host_metrics,
         ));
 
-        serve("Metrics", router, settings.server().metrics_port).await
+        serve("Admin", router, settings.server().metrics_port).await
     };
 
     let app = async {
-        let req_id = HeaderName::from_static(REQUEST_ID);
+        let req_id = correlation::REQUEST_ID_HEADER;
         let router = router::setup_app_router()
             .route_layer(axum::middleware::from_fn(middleware::metrics::track))
+            // Elevate the log level within requests with a valid debug
+            // header.
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(debug_requests),
+                middleware::debug_request::elevate,
+            ))
             .layer(Extension(env))
+            // Store the request id, and trace and span ids, in the request
+            // span's storage, for every log line within the request.
+            .layer(axum::middleware::from_fn(
+                middleware::correlation::correlate,
+            ))
+            // Log allowed baggage entries, as extracted with the configured
+            // propagators by the `OtelAxumLayer`.
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(settings.otel().logged_baggage.clone()),
+                middleware::propagation::log_baggage,
+            ))
             // Include trace context as header into the response.
             .layer(OtelInResponseLayer)
             // Opentelemetry tracing middleware.
             // This returns a `TraceLayer` configured to use
             // OpenTelemetry’s conventional span field names.
             .layer(OtelAxumLayer::default())
-            // Set and propagate "request_id" (as a ulid) per request.
+            // Set and propagate "request_id" per request: a trusted, valid
+            // incoming id, or a generated ulid.
             .layer(
                 ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn_with_state(
+                        Arc::new(request_ids),
+                        middleware::request_id::accept,
+                    ))
                     .set_request_id(req_id.clone(), MakeRequestUlid)
                     .propagate_request_id(req_id),
             )
@@ -98,15 +156,24 @@ async fn main() -> Result<()> {
             // `500 Internal Server` responses.
             .layer(CatchPanicLayer::custom(runtime::catch_panic))
             // Mark headers as sensitive on both requests and responses.
-            .layer(SetSensitiveHeadersLayer::new(once(
-                HeaderName::from_static(header::AUTHORIZATION.as_str()),
-            )))
+            .layer(SetSensitiveHeadersLayer::new(sensitive_headers))
             .merge(SwaggerUi::new("/swagger-ui").url("/api-doc/openapi.json", ApiDoc::openapi()));
 
         serve("Application", router, settings.server().port).await
     };
 
-    tokio::try_join!(app, app_metrics)?;
+    let served = tokio::try_join!(app, app_admin);
+
+    // Flush buffered spans and log records, blocking on their exporters.
+    tokio::task::spawn_blocking(move || {
+        if let Some(provider) = logger_provider {
+            let _ = provider.shutdown();
+        }
+        global::shutdown_tracer_provider();
+    })
+    .await?;
+
+    served?;
     Ok(())
 }
 
@@ -151,16 +218,104 @@ async fn shutdown() {
     }
 }
 
+/// Log filter directive used when `RUST_LOG` is unset or invalid.
+const DEFAULT_LOG_DIRECTIVE: &str =
+    "{{crate_name}}=info,tower_http=info,reqwest_retry=info,axum_tracing_opentelemetry=info";
+
 /// Setup all [tracing][tracing] layers for storage, request/response tracing,
 /// logging and metrics.
+///
+/// Returns the [LogFilter] of log output layers, to change at runtime, which
+/// debug requests can elevate up to `max_elevation`, the guards of
+/// non-blocking log writers, to keep for as long as logs are written, and
+/// the [LoggerProvider] exporting logs over OTLP, if any, to shut down on
+/// exit.
 fn setup_tracing(
-    writer: tracing_appender::non_blocking::NonBlocking,
+    settings_logging: &Logging,
     settings_otel: &Otel,
-) -> Result<()> {
-    let tracer = init_tracer(settings_otel)?;
+    environment: AppEnvironment,
+    max_elevation: LevelFilter,
+) -> Result<(LogFilter, Vec<WorkerGuard>, Option<LoggerProvider>)> {
+    let tracer = init_tracer(settings_otel, environment)?;
+
+    // An invalid RUST_LOG falls back to the default directive, with a
+    // warning once logging is set up, rather than aborting startup.
+    let (log_filter, invalid_rust_log) = match std::env::var("RUST_LOG") {
+        Ok(directive) => match LogFilter::new(directive.as_str()) {
+            Ok(log_filter) => (log_filter, None),
+            Err(err) => (LogFilter::new(DEFAULT_LOG_DIRECTIVE)?, Some(err)),
+        },
+        Err(_) => (LogFilter::new(DEFAULT_LOG_DIRECTIVE)?, None),
+    };
+    let log_filter = log_filter.with_max_elevation(max_elevation);
+
+    // Log sinks follow the runtime log filter, unless they set their own
+    // level.
+    let sink_filter = |level: &Option<String>| -> Result<LogFilter> {
+        match level {
+            Some(directive) => {
+                Ok(LogFilter::new(directive.as_str())?.with_max_elevation(max_elevation))
+            }
+            None => Ok(log_filter.clone()),
+        }
+    };
+
+    let mut writers = Vec::new();
+    if settings_logging.stdout.enabled {
+        writers.push((
+            tracing_appender::non_blocking(io::stdout()),
+            sink_filter(&settings_logging.stdout.level)?,
+        ));
+    }
+    for file in &settings_logging.files {
+        writers.push((log_file::non_blocking(file)?, sink_filter(&file.level)?));
+    }
+
+    // Log layers write the request's correlation ids on its events, even
+    // where their filter disables the request span itself.
+    let storage_layer =
+        StorageLayer::default().keep_event_fields(&settings_logging.fields.keep_event);
+    let correlation_fields = storage_layer.reader(correlation::FIELDS);
+
+    let mut guards = Vec::new();
+    let mut log_layers = Vec::new();
+    for ((writer, guard), filter) in writers {
+        guards.push(guard);
+        log_layers.push(
+            LogFmtLayer::new(writer)
+                .with_target(true)
+                .with_format(settings_logging.format)
+                .with_sampler(LogSampler::new(&settings_logging.sampling)?)
+                .with_span_events(settings_logging.fmt_span())
+                .with_span_fields(
+                    SpanFields::default()
+                        .promote(&settings_logging.fields.promote)
+                        .skip(&settings_logging.fields.skip),
+                )
+                .with_storage_reader(correlation_fields.clone())
+                .with_filter(filter.filter())
+                .boxed(),
+        );
+    }
+
+    // Optionally export log events over OTLP, alongside traces, except for
+    // those of the exporter itself.
+    let logger_provider = if settings_otel.exports_logs() {
+        Some(init_logger_provider(settings_otel, environment)?)
+    } else {
+        None
+    };
+    let otel_log_layer = logger_provider.as_ref().map(|provider| {
+        OtelLogLayer::new(provider)
+            .with_storage_reader(correlation_fields.clone())
+            .with_filter(log_filter.filter())
+            .with_filter(filter_fn(|metadata| {
+                !otel_log_layer::is_exporter_target(metadata.target())
+            }))
+    });
 
     let registry = tracing_subscriber::Registry::default()
-        .with(StorageLayer.with_filter(LevelFilter::TRACE))
+        .with(storage_layer.with_filter(LevelFilter::TRACE))
         .with(
             tracing_opentelemetry::layer()
                 .with_tracer(tracer)
@@ -173,14 +328,8 @@ fn setup_tracing(
                         .unwrap_or_default()
                 })),
         )
-        .with(LogFmtLayer::new(writer).with_target(true).with_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                EnvFilter::new(
-                    std::env::var("RUST_LOG")
-                        .unwrap_or_else(|_| "{{crate_name}}=info,tower_http=info,reqwest_retry=info,axum_tracing_opentelemetry=info".into()),
-                )
-            }),
-        ))
+        .with(log_layers)
+        .with(otel_log_layer)
         .with(
             MetricsLayer
                 .with_filter(LevelFilter::TRACE)
@@ -207,5 +356,13 @@ fn setup_tracing(
         registry.init();
     }
 
-    Ok(())
+    if let Some(err) = invalid_rust_log {
+        warn!(
+            subject = "app_settings",
+            category = "init",
+            "ignoring invalid RUST_LOG, using `{DEFAULT_LOG_DIRECTIVE}`: {err}"
+        );
+    }
+
+    Ok((log_filter, guards, logger_provider))
 }
@@ -0,0 +1,106 @@
+//! Global allocator wrapper tracking heap allocations, reported as process
+//! metrics when installed.
+//!
+//! Enabled with the `alloc-metrics` feature, which installs a
+//! [TrackingAllocator] over the [System] allocator in the application binary.
+
+use crate::metrics::registry::MetricDescription;
+use metrics::Unit;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// [GlobalAlloc] wrapper counting allocations and bytes currently allocated.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(System);
+/// ```
+#[derive(Debug, Default)]
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Wrap the `inner` allocator.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+            ALLOCATED.fetch_add(new_size as u64, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Descriptions of allocator metrics.
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::gauge(
+            "process_heap_allocated_bytes",
+            "Heap bytes currently allocated.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::counter("process_heap_allocations_total", "Total heap allocations.")
+            .with_unit(Unit::Count),
+    ]
+}
+
+/// Record allocator metrics.
+pub(crate) fn collect() {
+    metrics::gauge!("process_heap_allocated_bytes").set(ALLOCATED.load(Ordering::Relaxed) as f64);
+    metrics::counter!("process_heap_allocations_total")
+        .absolute(ALLOCATIONS.load(Ordering::Relaxed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_allocations() {
+        let allocator = TrackingAllocator::new(System);
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert!(ALLOCATIONS.load(Ordering::Relaxed) > allocations);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
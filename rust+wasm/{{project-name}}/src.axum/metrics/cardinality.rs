@@ -0,0 +1,146 @@
+//! Cardinality protection for metric labels.
+//!
+//! Label values coming from requests (paths) or instrumented code
+//! (`metric_label_*` fields) are unbounded, and every distinct value creates a
+//! new series. The [CardinalityGuard] caps the number of distinct values
+//! kept per metric and label, collapsing any further values into
+//! [OVERFLOW_VALUE] and counting them in `metrics_label_overflow_total`.
+
+use crate::metrics::registry::MetricDescription;
+use metrics::Unit;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+
+/// Default maximum of distinct values per metric label.
+pub const DEFAULT_MAX_LABEL_VALUES: usize = 100;
+
+/// Label value for requests not matching any route (e.g. 404s), instead of
+/// the raw request path.
+pub const UNMATCHED_PATH: &str = "unmatched";
+
+/// Label value replacing values past the cardinality limit.
+pub const OVERFLOW_VALUE: &str = "overflow";
+
+/// Counter of label values collapsed into [OVERFLOW_VALUE].
+pub const OVERFLOW_METRIC: &str = "metrics_label_overflow_total";
+
+static GUARD: OnceCell<CardinalityGuard> = OnceCell::new();
+
+static DEFAULT_GUARD: Lazy<CardinalityGuard> =
+    Lazy::new(|| CardinalityGuard::new(DEFAULT_MAX_LABEL_VALUES));
+
+/// Install the process-wide [CardinalityGuard] used by metrics middleware and
+/// the [MetricsLayer](crate::tracing_layers::metrics_layer::MetricsLayer).
+///
+/// Returns the guard back if one was already installed.
+pub fn init(guard: CardinalityGuard) -> Result<(), CardinalityGuard> {
+    GUARD.set(guard)
+}
+
+/// Installed [CardinalityGuard], or one with [DEFAULT_MAX_LABEL_VALUES] if
+/// [init] was never called.
+pub(crate) fn guard() -> &'static CardinalityGuard {
+    GUARD.get().unwrap_or(&DEFAULT_GUARD)
+}
+
+/// Descriptions of metrics recorded by the [CardinalityGuard].
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![MetricDescription::counter(
+        OVERFLOW_METRIC,
+        "Metric label values dropped for exceeding the cardinality limit.",
+    )
+    .with_unit(Unit::Count)]
+}
+
+/// Caps distinct label values per metric and label.
+#[derive(Debug)]
+pub struct CardinalityGuard {
+    max_values: usize,
+    seen: RwLock<HashMap<(String, String), HashSet<String>>>,
+}
+
+impl CardinalityGuard {
+    /// Create a new [CardinalityGuard], keeping up to `max_values` distinct
+    /// values per metric and label.
+    pub fn new(max_values: usize) -> Self {
+        Self {
+            max_values,
+            seen: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Guard a single label `value` of `metric`, returning it as is if
+    /// already seen or still under the limit, or [OVERFLOW_VALUE] otherwise.
+    pub fn label(&self, metric: &str, label: &str, value: String) -> String {
+        let key = (metric.to_string(), label.to_string());
+        if let Some(values) = self.seen.read().get(&key) {
+            if values.contains(&value) {
+                return value;
+            }
+        }
+
+        let mut seen = self.seen.write();
+        let values = seen.entry(key).or_default();
+        if values.contains(&value) || values.len() < self.max_values {
+            values.insert(value.clone());
+            return value;
+        }
+        drop(seen);
+
+        metrics::counter!(
+            OVERFLOW_METRIC,
+            "metric" => metric.to_string(),
+            "label" => label.to_string()
+        )
+        .increment(1);
+
+        OVERFLOW_VALUE.to_string()
+    }
+
+    /// Guard all `labels` of `metric`.
+    pub fn labels<K: AsRef<str>>(
+        &self,
+        metric: &str,
+        labels: Vec<(K, String)>,
+    ) -> Vec<(K, String)> {
+        labels
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self.label(metric, key.as_ref(), value);
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_counter, test_utils::LocalRecorder};
+
+    #[test]
+    fn collapses_values_past_limit() {
+        let _recorder = LocalRecorder::install();
+        let guard = CardinalityGuard::new(2);
+
+        let path = |value: &str| guard.label("requests_total", "request_path", value.into());
+        assert_eq!(path("/a"), "/a");
+        assert_eq!(path("/b"), "/b");
+        assert_eq!(path("/a"), "/a");
+        assert_eq!(path("/c"), OVERFLOW_VALUE);
+        assert_eq!(path("/d"), OVERFLOW_VALUE);
+
+        // Limits are per metric and label.
+        assert_eq!(
+            guard.label("other_total", "request_path", "/c".into()),
+            "/c"
+        );
+
+        assert_counter!(
+            OVERFLOW_METRIC,
+            &[("label", "request_path"), ("metric", "requests_total")],
+            2
+        );
+    }
+}
@@ -1,106 +1,313 @@
-//! Server process metrics, including cpu, memory, disk, etc.
+//! Server process metrics, including cpu, memory, disk, file descriptors,
+//! threads and tokio runtime metrics, and optionally host and container
+//! metrics.
 
+use crate::metrics::{cgroup, registry::MetricDescription, runtime::RuntimeCollector};
 use anyhow::{anyhow, Context, Result};
-use metrics::{describe_gauge, Unit};
+use metrics::Unit;
 use std::time::Duration;
-use sysinfo::{get_current_pid, ProcessExt, System, SystemExt};
+use sysinfo::{get_current_pid, CpuExt, NetworkExt, Pid, ProcessExt, System, SystemExt};
+use tokio::runtime::Handle;
 use tracing::{info, warn};
 
-/// Create and describe gauges for process metrics.
-pub(crate) fn describe() {
-    describe_gauge!(
-        "process_cpu_usage_percentage",
-        Unit::Percent,
-        "The CPU percentage used."
-    );
-    describe_gauge!(
-        "process_virtual_memory_bytes",
-        Unit::Bytes,
-        "The virtual memory size in bytes."
-    );
-    describe_gauge!("process_memory_bytes", Unit::Bytes, "Memory size in bytes.");
-    describe_gauge!(
-        "process_disk_total_written_bytes",
-        Unit::Bytes,
-        "The total bytes written to disk."
-    );
-    describe_gauge!(
-        "process_disk_written_bytes",
-        Unit::Bytes,
-        "The bytes written to disk."
-    );
-    describe_gauge!(
-        "process_disk_total_read_bytes",
-        Unit::Bytes,
-        "Total bytes Read from disk."
-    );
-    describe_gauge!(
-        "process_disk_read_bytes",
-        Unit::Bytes,
-        "The bytes read from disk."
-    );
-    describe_gauge!(
-        "process_disk_written_bytes",
-        Unit::Bytes,
-        "The bytes written to disk."
-    );
-    describe_gauge!(
-        "process_uptime_seconds",
-        Unit::Seconds,
-        "How much time the process has been running in seconds."
-    );
+/// Descriptions of process metrics.
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::gauge("process_cpu_usage_percentage", "The CPU percentage used.")
+            .with_unit(Unit::Percent),
+        MetricDescription::gauge(
+            "process_virtual_memory_bytes",
+            "The virtual memory size in bytes.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge("process_memory_bytes", "Memory size in bytes.")
+            .with_unit(Unit::Bytes),
+        MetricDescription::gauge(
+            "process_disk_total_written_bytes",
+            "The total bytes written to disk.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge("process_disk_written_bytes", "The bytes written to disk.")
+            .with_unit(Unit::Bytes),
+        MetricDescription::gauge(
+            "process_disk_total_read_bytes",
+            "Total bytes read from disk.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge("process_disk_read_bytes", "The bytes read from disk.")
+            .with_unit(Unit::Bytes),
+        MetricDescription::gauge(
+            "process_uptime_seconds",
+            "How much time the process has been running in seconds.",
+        )
+        .with_unit(Unit::Seconds),
+        MetricDescription::gauge("process_open_fds", "Number of open file descriptors.")
+            .with_unit(Unit::Count),
+        MetricDescription::gauge(
+            "process_max_fds",
+            "Maximum number of open file descriptors (soft limit).",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::gauge("process_threads", "Number of OS threads in the process.")
+            .with_unit(Unit::Count),
+    ]
+}
+
+/// Descriptions of host metrics, collected if enabled in
+/// [Monitoring](crate::settings::Monitoring) settings.
+pub(crate) fn host_descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::gauge(
+            "host_cpu_usage_percentage",
+            "The CPU percentage used across all cores of the host.",
+        )
+        .with_unit(Unit::Percent),
+        MetricDescription::gauge(
+            "host_memory_total_bytes",
+            "Total memory of the host in bytes.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge(
+            "host_memory_used_bytes",
+            "Memory used on the host in bytes.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge("host_load_average_1m", "Host load average over 1 minute."),
+        MetricDescription::counter(
+            "host_network_received_bytes_total",
+            "Total bytes received per network interface.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::counter(
+            "host_network_transmitted_bytes_total",
+            "Total bytes transmitted per network interface.",
+        )
+        .with_unit(Unit::Bytes),
+    ]
 }
 
 /// Collection process metrics on a settings-defined interval.
-pub async fn collect_metrics(interval: u64) {
+///
+/// Metrics of the tokio runtime the collection runs on, and of the
+/// [TrackingAllocator](crate::metrics::allocator::TrackingAllocator) (with
+/// the `alloc-metrics` feature), are collected on the same tick, as are host
+/// and container (cgroup) metrics if `host_metrics` is set.
+pub async fn collect_metrics(interval: u64, host_metrics: bool) {
     let mut interval = tokio::time::interval(Duration::from_secs(interval));
+    let mut runtime = RuntimeCollector::new(Handle::current());
+    let mut process = match ProcessCollector::new(host_metrics) {
+        Ok(process) => Some(process),
+        Err(err) => {
+            warn!(
+                subject = "metrics.process_collection",
+                category = "metrics",
+                "failure to set up process collection {:#?}",
+                err
+            );
+            None
+        }
+    };
 
     loop {
         interval.tick().await;
-        let sys_info = System::new();
-        if let Err(err) = get_proc_stats(sys_info).await {
+        if let Some(process) = process.as_mut() {
+            if let Err(err) = process.collect() {
+                warn!(
+                    subject = "metrics.process_collection",
+                    category = "metrics",
+                    "failure to get process statistics {:#?}",
+                    err
+                );
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Err(err) = get_proc_fs_stats() {
             warn!(
                 subject = "metrics.process_collection",
                 category = "metrics",
-                "failure to get process statistics {:#?}",
+                "failure to get /proc statistics {:#?}",
                 err
             );
         }
+
+        runtime.collect();
+
+        #[cfg(feature = "alloc-metrics")]
+        crate::metrics::allocator::collect();
     }
 }
 
-async fn get_proc_stats(mut sys: System) -> Result<()> {
-    let pid = get_current_pid().map_err(|e| anyhow!("no process pid found {}", e))?;
-
-    let is_process_refreshed = sys.refresh_process(pid);
-
-    if is_process_refreshed {
-        let proc = sys.process(pid).context("no process associated with pid")?;
-        let cpus = num_cpus::get();
-        let disk = proc.disk_usage();
-
-        // cpu-usage divided by # of cores.
-        metrics::gauge!("process_cpu_usage_percentage")
-            .set(f64::from(proc.cpu_usage() / (cpus as f32)));
-
-        // The docs for sysinfo indicate that `virtual_memory`
-        // returns in KB, but that is incorrect.
-        // See this issue: https://github.com/GuillaumeGomez/sysinfo/issues/428#issuecomment-774098021
-        // And this PR: https://github.com/GuillaumeGomez/sysinfo/pull/430/files
-        metrics::gauge!("process_virtual_memory_bytes").set(proc.virtual_memory() as f64);
-        metrics::gauge!("process_memory_bytes").set((proc.memory()) as f64);
-        metrics::gauge!("process_uptime_seconds").set(proc.run_time() as f64);
-        metrics::gauge!("process_disk_total_written_bytes").set(disk.total_written_bytes as f64);
-        metrics::gauge!("process_disk_written_bytes").set(disk.written_bytes as f64);
-        metrics::gauge!("process_disk_total_read_bytes").set(disk.total_read_bytes as f64);
-        metrics::gauge!("process_disk_read_bytes").set(disk.read_bytes as f64);
-    } else {
-        info!(
-            subject = "metrics.process_collection",
-            category = "metrics",
-            "failed to refresh process information, metrics may show old results"
-        );
+/// Collector of process, and optionally host and cgroup, metrics.
+///
+/// Keeps its [System] across collections, as sysinfo computes CPU usage
+/// from the difference between two refreshes.
+#[derive(Debug)]
+pub(crate) struct ProcessCollector {
+    sys: System,
+    pid: Pid,
+    host_metrics: bool,
+}
+
+impl ProcessCollector {
+    /// Create a [ProcessCollector] for the current process, with an initial
+    /// refresh for CPU usage to be computed from.
+    pub(crate) fn new(host_metrics: bool) -> Result<Self> {
+        let pid = get_current_pid().map_err(|e| anyhow!("no process pid found {}", e))?;
+
+        let mut sys = System::new();
+        sys.refresh_cpu();
+        sys.refresh_process(pid);
+        if host_metrics {
+            sys.refresh_networks_list();
+        }
+
+        Ok(Self {
+            sys,
+            pid,
+            host_metrics,
+        })
+    }
+
+    /// Refresh and record metrics.
+    pub(crate) fn collect(&mut self) -> Result<()> {
+        self.collect_process()?;
+
+        if self.host_metrics {
+            self.collect_host();
+            cgroup::collect();
+        }
+
+        Ok(())
     }
 
+    fn collect_process(&mut self) -> Result<()> {
+        let is_process_refreshed = self.sys.refresh_process(self.pid);
+
+        if is_process_refreshed {
+            let proc = self
+                .sys
+                .process(self.pid)
+                .context("no process associated with pid")?;
+            let cpus = num_cpus::get();
+            let disk = proc.disk_usage();
+
+            // cpu-usage divided by # of cores.
+            metrics::gauge!("process_cpu_usage_percentage")
+                .set(f64::from(proc.cpu_usage() / (cpus as f32)));
+
+            // The docs for sysinfo indicate that `virtual_memory`
+            // returns in KB, but that is incorrect.
+            // See this issue: https://github.com/GuillaumeGomez/sysinfo/issues/428#issuecomment-774098021
+            // And this PR: https://github.com/GuillaumeGomez/sysinfo/pull/430/files
+            metrics::gauge!("process_virtual_memory_bytes").set(proc.virtual_memory() as f64);
+            metrics::gauge!("process_memory_bytes").set((proc.memory()) as f64);
+            metrics::gauge!("process_uptime_seconds").set(proc.run_time() as f64);
+            metrics::gauge!("process_disk_total_written_bytes")
+                .set(disk.total_written_bytes as f64);
+            metrics::gauge!("process_disk_written_bytes").set(disk.written_bytes as f64);
+            metrics::gauge!("process_disk_total_read_bytes").set(disk.total_read_bytes as f64);
+            metrics::gauge!("process_disk_read_bytes").set(disk.read_bytes as f64);
+        } else {
+            info!(
+                subject = "metrics.process_collection",
+                category = "metrics",
+                "failed to refresh process information, metrics may show old results"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn collect_host(&mut self) {
+        self.sys.refresh_cpu();
+        self.sys.refresh_memory();
+        // Also picks up interfaces created since the last collection.
+        self.sys.refresh_networks_list();
+
+        metrics::gauge!("host_cpu_usage_percentage")
+            .set(f64::from(self.sys.global_cpu_info().cpu_usage()));
+        metrics::gauge!("host_memory_total_bytes").set(self.sys.total_memory() as f64);
+        metrics::gauge!("host_memory_used_bytes").set(self.sys.used_memory() as f64);
+        metrics::gauge!("host_load_average_1m").set(self.sys.load_average().one);
+
+        for (interface, network) in self.sys.networks() {
+            metrics::counter!("host_network_received_bytes_total", "interface" => interface.to_string())
+                .absolute(network.total_received());
+            metrics::counter!("host_network_transmitted_bytes_total", "interface" => interface.to_string())
+                .absolute(network.total_transmitted());
+        }
+    }
+}
+
+/// Open file descriptors, their limit and threads, read from `/proc/self`.
+#[cfg(target_os = "linux")]
+fn get_proc_fs_stats() -> Result<()> {
+    let open_fds = std::fs::read_dir("/proc/self/fd")?.count();
+    metrics::gauge!("process_open_fds").set(open_fds as f64);
+
+    if let Some(max_fds) = parse_max_fds(&std::fs::read_to_string("/proc/self/limits")?) {
+        metrics::gauge!("process_max_fds").set(max_fds as f64);
+    }
+
+    let threads = parse_threads(&std::fs::read_to_string("/proc/self/status")?)
+        .context("no thread count in /proc/self/status")?;
+    metrics::gauge!("process_threads").set(threads as f64);
+
     Ok(())
 }
+
+/// Soft limit of open files, from `/proc/<pid>/limits`, if not unlimited.
+#[cfg(any(target_os = "linux", test))]
+fn parse_max_fds(limits: &str) -> Option<u64> {
+    limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max open files"))
+        .and_then(|limit| limit.split_whitespace().next())
+        .and_then(|soft| soft.parse().ok())
+}
+
+/// Thread count, from `/proc/<pid>/status`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_threads(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))
+        .and_then(|threads| threads.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::LocalRecorder;
+
+    #[test]
+    fn parses_proc_fs_stats() {
+        let limits = "Limit                     Soft Limit           Hard Limit           Units\n\
+                      Max open files            1024                 1048576              files\n";
+        assert_eq!(parse_max_fds(limits), Some(1024));
+        assert_eq!(
+            parse_max_fds(
+                "Max open files            unlimited            unlimited            files"
+            ),
+            None
+        );
+
+        let status = "Name:\tapp\nThreads:\t12\nSigQ:\t0/63432\n";
+        assert_eq!(parse_threads(status), Some(12));
+    }
+
+    #[test]
+    fn collects_process_and_host_metrics() {
+        let recorder = LocalRecorder::install();
+        let mut collector = ProcessCollector::new(true).unwrap();
+
+        collector.collect().unwrap();
+        collector.collect().unwrap();
+
+        assert!(recorder.gauge("process_memory_bytes", &[]).unwrap() > 0.0);
+        assert!(recorder.gauge("host_memory_total_bytes", &[]).unwrap() > 0.0);
+        let cpu = recorder.gauge("process_cpu_usage_percentage", &[]).unwrap();
+        assert!(cpu >= 0.0);
+    }
+}
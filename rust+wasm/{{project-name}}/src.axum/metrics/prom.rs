@@ -1,23 +1,123 @@
 //! Metrics Prometheus recorder.
 
-use crate::metrics::process;
-
+use crate::{
+    metrics::{
+        otlp::{init_meter_provider, OtlpRecorder},
+        registry,
+    },
+    settings::{AppEnvironment, MetricMatcher, Metrics, Otel},
+};
+use anyhow::anyhow;
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use metrics_util::{layers::FanoutBuilder, MetricKindMask};
+
+/// Histogram buckets, in seconds, for `_duration_seconds` metrics.
+pub(crate) const EXPONENTIAL_SECONDS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Sets up Prometheus buckets, quantiles, global labels and idle timeout from
+/// [Metrics] settings, installs recorder and describes all metrics in the
+/// [registry].
+///
+/// Histograms registered with custom buckets use those, while other
+/// histograms use the buckets of a matching [MetricMatcher] in settings
+/// (by default, [EXPONENTIAL_SECONDS] for `_duration_seconds`
+/// metrics), or are rendered as summaries.
+///
+/// If enabled in [Otel] settings, metrics are also pushed over OTLP, by
+/// installing the Prometheus recorder alongside an [OtlpRecorder].
+pub fn setup_metrics_recorder(
+    settings: &Otel,
+    metrics_settings: &Metrics,
+    environment: AppEnvironment,
+) -> anyhow::Result<PrometheusHandle> {
+    let recorder = prometheus_builder(metrics_settings, environment)?.build_recorder();
+    let handle = recorder.handle();
 
-/// Sets up Prometheus buckets for matched metrics and installs recorder.
-pub fn setup_metrics_recorder() -> anyhow::Result<PrometheusHandle> {
-    const EXPONENTIAL_SECONDS: &[f64] = &[
-        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
-    ];
+    if settings.exports_metrics() {
+        let provider = init_meter_provider(settings, environment)?;
+        let fanout = FanoutBuilder::default()
+            .add_recorder(recorder)
+            .add_recorder(OtlpRecorder::new(&provider))
+            .build();
+        metrics::set_global_recorder(fanout)
+            .map_err(|e| anyhow!("failed to install metrics recorder: {e}"))?;
+    } else {
+        metrics::set_global_recorder(recorder)
+            .map_err(|e| anyhow!("failed to install metrics recorder: {e}"))?;
+    }
 
-    let builder = PrometheusBuilder::new()
-        .set_buckets_for_metric(
-            Matcher::Suffix("_duration_seconds".to_string()),
-            EXPONENTIAL_SECONDS,
-        )?
-        .install_recorder()?;
+    registry::describe_all();
 
-    process::describe();
+    Ok(handle)
+}
+
+/// [PrometheusBuilder] configured from [Metrics] settings and the [registry].
+fn prometheus_builder(
+    metrics_settings: &Metrics,
+    environment: AppEnvironment,
+) -> anyhow::Result<PrometheusBuilder> {
+    let mut builder = PrometheusBuilder::new()
+        .set_quantiles(&metrics_settings.quantiles)?
+        .idle_timeout(MetricKindMask::ALL, metrics_settings.idle_timeout())
+        .add_global_label("service", env!("CARGO_PKG_NAME"))
+        .add_global_label("environment", environment.to_string())
+        .add_global_label("version", env!("CARGO_PKG_VERSION"));
+    for (key, value) in &metrics_settings.global_labels {
+        builder = builder.add_global_label(key, value);
+    }
+    for buckets in &metrics_settings.buckets {
+        builder = builder.set_buckets_for_metric(matcher(&buckets.matcher), &buckets.buckets)?;
+    }
+    for (name, buckets) in registry::histogram_buckets() {
+        builder = builder.set_buckets_for_metric(Matcher::Full(name), &buckets)?;
+    }
 
     Ok(builder)
 }
+
+fn matcher(matcher: &MetricMatcher) -> Matcher {
+    match matcher {
+        MetricMatcher::Full(name) => Matcher::Full(name.to_string()),
+        MetricMatcher::Prefix(prefix) => Matcher::Prefix(prefix.to_string()),
+        MetricMatcher::Suffix(suffix) => Matcher::Suffix(suffix.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MetricBuckets;
+    use std::collections::HashMap;
+
+    #[test]
+    fn applies_metrics_settings() {
+        let settings = Metrics {
+            buckets: vec![MetricBuckets {
+                matcher: MetricMatcher::Prefix("db_".to_string()),
+                buckets: vec![0.1, 1.0],
+            }],
+            quantiles: vec![0.5, 0.99],
+            global_labels: HashMap::from([("region".to_string(), "eu".to_string())]),
+            idle_timeout_secs: Some(60),
+        };
+        let recorder = prometheus_builder(&settings, AppEnvironment::Local)
+            .unwrap()
+            .build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            metrics::histogram!("db_query_seconds").record(0.5);
+            metrics::histogram!("render_seconds").record(0.5);
+        });
+        let rendered = handle.render();
+
+        assert!(rendered.contains(r#"db_query_seconds_bucket{service="#));
+        assert!(rendered.contains(r#"le="0.1"}"#));
+        assert!(rendered.contains(r#"environment="local""#));
+        assert!(rendered.contains(r#"region="eu""#));
+        assert!(rendered.contains("# TYPE render_seconds summary"));
+        assert!(rendered.contains(r#"quantile="0.99""#));
+    }
+}
@@ -0,0 +1,161 @@
+//! Middleware authenticating admin server requests with the configured
+//! [AdminAuth] scheme, and identifying the [AdminCaller] of each request.
+
+use crate::{error::AppError, settings::AdminAuth};
+use axum::{
+    extract::{Request, State},
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use headers::{
+    authorization::{Basic, Bearer},
+    Authorization, HeaderMapExt,
+};
+use std::fmt;
+use subtle::ConstantTimeEq;
+
+/// Identity of an admin request's caller, as authenticated by
+/// [authenticate] and added to request extensions. Requests to an admin
+/// server without authentication carry none, and are [AdminCaller::Anonymous].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AdminCaller {
+    /// Unauthenticated caller.
+    #[default]
+    Anonymous,
+    /// Caller authenticated with basic credentials of the given username.
+    User(String),
+    /// Caller authenticated with the bearer token.
+    Bearer,
+}
+
+impl fmt::Display for AdminCaller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "anonymous"),
+            Self::User(username) => write!(f, "user:{username}"),
+            Self::Bearer => write!(f, "bearer"),
+        }
+    }
+}
+
+/// Middleware function rejecting requests without valid credentials for
+/// `auth` with a `401 Unauthorized` response, and adding the [AdminCaller]
+/// to authorized requests.
+pub async fn authenticate(
+    State(auth): State<AdminAuth>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let authorized = match &auth {
+        AdminAuth::Basic { username, password } => headers
+            .typed_get::<Authorization<Basic>>()
+            .map_or(false, |credentials| {
+                // Compare both, so timing doesn't tell which one mismatched.
+                let user = secure_eq(credentials.username(), username);
+                let pass = secure_eq(credentials.password(), password);
+                user & pass
+            }),
+        AdminAuth::Bearer { token } => headers
+            .typed_get::<Authorization<Bearer>>()
+            .map_or(false, |credentials| secure_eq(credentials.token(), token)),
+    };
+
+    if authorized {
+        let caller = match &auth {
+            AdminAuth::Basic { username, .. } => AdminCaller::User(username.clone()),
+            AdminAuth::Bearer { .. } => AdminCaller::Bearer,
+        };
+        request.extensions_mut().insert(caller);
+        return next.run(request).await;
+    }
+
+    let challenge = match auth {
+        AdminAuth::Basic { .. } => r#"Basic realm="admin""#,
+        AdminAuth::Bearer { .. } => r#"Bearer realm="admin""#,
+    };
+    (
+        [(WWW_AUTHENTICATE, challenge)],
+        AppError::new(StatusCode::UNAUTHORIZED, Some("Invalid credentials")),
+    )
+        .into_response()
+}
+
+fn secure_eq(given: &str, expected: &str) -> bool {
+    given.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::parse_error;
+    use axum::{body::Body, http::header::AUTHORIZATION, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    async fn request(auth: AdminAuth, authorization: Option<&str>) -> Response {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(caller): Extension<AdminCaller>| async move { caller.to_string() }),
+            )
+            .layer(axum::middleware::from_fn_with_state(auth, authenticate));
+
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn authenticates_basic() {
+        let auth = AdminAuth::Basic {
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+        };
+
+        // admin:secret
+        let res = request(auth.clone(), Some("Basic YWRtaW46c2VjcmV0")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "user:admin");
+
+        // admin:wrong
+        let res = request(auth.clone(), Some("Basic YWRtaW46d3Jvbmc=")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = request(auth, None).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()[WWW_AUTHENTICATE], r#"Basic realm="admin""#);
+        assert_eq!(
+            parse_error(res).await,
+            AppError::new(StatusCode::UNAUTHORIZED, Some("Invalid credentials"))
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticates_bearer() {
+        let auth = AdminAuth::Bearer {
+            token: "secret".to_string(),
+        };
+
+        let res = request(auth.clone(), Some("Bearer secret")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "bearer");
+
+        let res = request(auth.clone(), Some("Bearer other")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = request(auth, Some("Basic YWRtaW46c2VjcmV0")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,461 @@
+//! Middleware elevating the log level of a single request, e.g. to get
+//! `debug!` body logs for one customer request in production without raising
+//! the global level.
+//!
+//! Requests carrying a valid debug header (an allowlisted token, or an
+//! HMAC-signed expiring value, configured via
+//! [settings::DebugRequest](crate::settings::DebugRequest)) are handled within
+//! a [DebugRequest] scope. Within it, the
+//! [LogFilter](crate::tracing_layers::log_filter::LogFilter) enables spans and
+//! events up to the elevated level, and the reqwest
+//! [Logger](crate::middleware::logging::Logger) logs bodies of outbound calls
+//! and, for allowlisted internal hosts only, forwards a signed header, for
+//! end-to-end debugging. Allowlisted tokens are never forwarded.
+//!
+//! The scope follows the request's future, including spans created within
+//! it, but not tasks spawned from it.
+
+use crate::settings;
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::{HeaderName, HeaderValue};
+use reqwest::Url;
+use ring::hmac;
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use subtle::ConstantTimeEq;
+use tracing::{info, level_filters::LevelFilter, warn};
+
+/// Purpose bound into signatures, so signatures made with the same key for
+/// anything else aren't valid debug header values.
+const SIGNING_PURPOSE: &str = "debug-request";
+/// Lifetime of values signed for forwarding, for requests elevated by an
+/// allowlisted token.
+const FORWARD_TTL: Duration = Duration::from_secs(300);
+
+tokio::task_local! {
+    static DEBUG_REQUEST: DebugRequest;
+}
+
+/// Elevated log level and forwarded debug header of the request being
+/// handled.
+#[derive(Clone, Debug)]
+pub struct DebugRequest {
+    level: LevelFilter,
+    header: HeaderName,
+    forward: Option<HeaderValue>,
+    forward_hosts: Arc<[String]>,
+}
+
+impl DebugRequest {
+    /// Elevated log level.
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Signed debug header to forward to an outbound call to `url`, if its
+    /// host is allowlisted.
+    pub fn forward_header(&self, url: &Url) -> Option<(&HeaderName, &HeaderValue)> {
+        let host = url.host_str()?;
+        let allowlisted = self.forward_hosts.iter().any(|allowed| {
+            host.eq_ignore_ascii_case(allowed)
+                || (allowed.starts_with('.')
+                    && host.len() > allowed.len()
+                    && host[host.len() - allowed.len()..].eq_ignore_ascii_case(allowed))
+        });
+        if !allowlisted {
+            return None;
+        }
+        self.forward.as_ref().map(|value| (&self.header, value))
+    }
+
+    /// Run `future` within the scope of this debug request, e.g. to keep the
+    /// elevated level in tasks spawned from the request.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        DEBUG_REQUEST.scope(self, future).await
+    }
+}
+
+/// [DebugRequest] of the current request, if elevated.
+pub fn current() -> Option<DebugRequest> {
+    DEBUG_REQUEST.try_with(Clone::clone).ok()
+}
+
+/// Elevated log level of the current request, if any.
+pub(crate) fn elevated_level() -> Option<LevelFilter> {
+    DEBUG_REQUEST.try_with(|request| request.level).ok()
+}
+
+/// Validates debug headers against the allowlisted tokens and signing key.
+#[derive(Debug)]
+pub struct DebugRequests {
+    header: HeaderName,
+    level: LevelFilter,
+    tokens: Vec<String>,
+    signing_key: Option<hmac::Key>,
+    max_ttl: Duration,
+    forward_hosts: Arc<[String]>,
+}
+
+impl DebugRequests {
+    /// Create [DebugRequests] from [settings::DebugRequest], failing on an
+    /// invalid header name or level.
+    pub fn new(settings: &settings::DebugRequest) -> Result<Self> {
+        let header = HeaderName::from_str(&settings.header)
+            .map_err(|err| anyhow!("invalid debug request header: {err}"))?;
+        let level = LevelFilter::from_str(&settings.level)
+            .map_err(|err| anyhow!("invalid debug request level: {err}"))?;
+
+        Ok(Self {
+            header,
+            level,
+            tokens: settings.tokens.clone(),
+            signing_key: settings
+                .signing_key
+                .as_ref()
+                .map(|key| hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes())),
+            max_ttl: settings.max_ttl(),
+            forward_hosts: settings.forward_hosts.iter().cloned().collect(),
+        })
+    }
+
+    /// Whether any header value can be valid.
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || self.signing_key.is_some()
+    }
+
+    /// Debug header name.
+    pub fn header(&self) -> &HeaderName {
+        &self.header
+    }
+
+    /// Elevated log level, or [LevelFilter::OFF] if disabled.
+    pub fn max_level(&self) -> LevelFilter {
+        if self.is_enabled() {
+            self.level
+        } else {
+            LevelFilter::OFF
+        }
+    }
+
+    /// Sign a header value expiring after `ttl`, at most the maximum ttl,
+    /// if a signing key is set.
+    pub fn sign(&self, ttl: Duration) -> Option<String> {
+        let key = self.signing_key.as_ref()?;
+        let expiry = (SystemTime::now() + ttl.min(self.max_ttl))
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        let nonce = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+        let signature = hmac::sign(key, signed_message(expiry, &nonce).as_bytes());
+        Some(format!(
+            "{expiry}.{nonce}.{}",
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Whether `value` is an allowlisted token, or a value signed with the
+    /// signing key, unexpired and expiring within the maximum ttl.
+    pub fn verify(&self, value: &str) -> bool {
+        let allowlisted = self
+            .tokens
+            .iter()
+            .any(|token| bool::from(token.as_bytes().ct_eq(value.as_bytes())));
+
+        allowlisted || self.verify_signed(value)
+    }
+
+    fn verify_signed(&self, value: &str) -> bool {
+        let Some(key) = &self.signing_key else {
+            return false;
+        };
+        let mut parts = value.splitn(3, '.');
+        let (Some(expiry), Some(nonce), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return false;
+        };
+        let (Ok(expiry), Ok(signature)) =
+            (expiry.parse::<u64>(), URL_SAFE_NO_PAD.decode(signature))
+        else {
+            return false;
+        };
+        let expires_at = UNIX_EPOCH + Duration::from_secs(expiry);
+        let now = SystemTime::now();
+        let in_window = expires_at > now && expires_at <= now + self.max_ttl;
+
+        in_window && hmac::verify(key, signed_message(expiry, nonce).as_bytes(), &signature).is_ok()
+    }
+
+    /// Header value to forward to outbound calls of a request elevated by
+    /// `value`: signed values as received, and a freshly signed value
+    /// instead of an allowlisted token.
+    fn forward_value(&self, value: &HeaderValue) -> Option<HeaderValue> {
+        if value
+            .to_str()
+            .map_or(false, |value| self.verify_signed(value))
+        {
+            return Some(value.clone());
+        }
+        self.sign(FORWARD_TTL)
+            .and_then(|signed| HeaderValue::from_str(&signed).ok())
+    }
+}
+
+fn signed_message(expiry: u64, nonce: &str) -> String {
+    format!("{SIGNING_PURPOSE}.{expiry}.{nonce}")
+}
+
+/// Middleware function handling requests with a valid debug header within a
+/// [DebugRequest] scope. Invalid headers are ignored, and logged.
+pub async fn elevate(
+    State(requests): State<Arc<DebugRequests>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(value) = request.headers().get(&requests.header).cloned() else {
+        return next.run(request).await;
+    };
+
+    if !value.to_str().map_or(false, |value| requests.verify(value)) {
+        warn!(
+            subject = "debug_request",
+            category = "http.request",
+            "ignoring invalid debug request header"
+        );
+        return next.run(request).await;
+    }
+
+    info!(
+        subject = "debug_request",
+        category = "http.request",
+        level = %requests.level,
+        "elevating log level for debug request"
+    );
+
+    let debug_request = DebugRequest {
+        level: requests.level,
+        header: requests.header.clone(),
+        forward: requests.forward_value(&value),
+        forward_hosts: requests.forward_hosts.clone(),
+    };
+    debug_request.scope(next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn debug_requests() -> DebugRequests {
+        DebugRequests::new(&settings::DebugRequest {
+            tokens: vec!["allowed".to_string()],
+            signing_key: Some("key".to_string()),
+            forward_hosts: vec!["127.0.0.1".to_string(), ".svc.internal".to_string()],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn signed_value(requests: &DebugRequests, message: &str) -> String {
+        let key = requests.signing_key.as_ref().unwrap();
+        URL_SAFE_NO_PAD.encode(hmac::sign(key, message.as_bytes()))
+    }
+
+    #[test]
+    fn verifies_tokens_and_signatures() {
+        let requests = debug_requests();
+        assert!(requests.verify("allowed"));
+        assert!(!requests.verify("other"));
+
+        let signed = requests.sign(Duration::from_secs(60)).unwrap();
+        assert!(requests.verify(&signed));
+        assert_ne!(signed, requests.sign(Duration::from_secs(60)).unwrap());
+
+        let (expiry, rest) = signed.split_once('.').unwrap();
+        let extended = format!("{}.{rest}", expiry.parse::<u64>().unwrap() + 60);
+        assert!(!requests.verify(&extended));
+
+        let expired = format!(
+            "1.nonce.{}",
+            signed_value(&requests, "debug-request.1.nonce")
+        );
+        assert!(!requests.verify(&expired));
+
+        // Expiring beyond the maximum ttl.
+        let expiry = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 7_200;
+        let message = format!("debug-request.{expiry}.nonce");
+        let distant = format!("{expiry}.nonce.{}", signed_value(&requests, &message));
+        assert!(!requests.verify(&distant));
+        let capped = requests.sign(Duration::from_secs(7_200)).unwrap();
+        assert!(requests.verify(&capped));
+
+        // Signatures of the expiry alone, or for another purpose.
+        let expiry = expiry - 3_600;
+        let unbound = format!(
+            "{expiry}.nonce.{}",
+            signed_value(&requests, &expiry.to_string())
+        );
+        assert!(!requests.verify(&unbound));
+        let message = format!("other.{expiry}.nonce");
+        let other = format!("{expiry}.nonce.{}", signed_value(&requests, &message));
+        assert!(!requests.verify(&other));
+
+        let disabled = DebugRequests::new(&settings::DebugRequest::default()).unwrap();
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.max_level(), LevelFilter::OFF);
+        assert!(!disabled.verify(""));
+    }
+
+    #[tokio::test]
+    async fn scopes_valid_debug_requests() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    current()
+                        .map(|request| request.level().to_string())
+                        .unwrap_or_default()
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(debug_requests()),
+                elevate,
+            ));
+
+        for (value, expected) in [(Some("allowed"), "debug"), (Some("other"), ""), (None, "")] {
+            let mut request = Request::builder().uri("/");
+            if let Some(value) = value {
+                request = request.header("x-debug-request", value);
+            }
+            let res = app
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[test]
+    fn forwards_signed_values_to_allowlisted_hosts() {
+        let requests = debug_requests();
+        let debug_request = |value: &str| DebugRequest {
+            level: LevelFilter::DEBUG,
+            header: requests.header.clone(),
+            forward: requests.forward_value(&HeaderValue::from_str(value).unwrap()),
+            forward_hosts: requests.forward_hosts.clone(),
+        };
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let signed = requests.sign(Duration::from_secs(60)).unwrap();
+        let elevated = debug_request(&signed);
+        for allowed in ["http://127.0.0.1:8080/", "https://api.svc.internal/"] {
+            let (name, value) = elevated.forward_header(&url(allowed)).unwrap();
+            assert_eq!(name, "x-debug-request");
+            assert_eq!(value, signed.as_str());
+        }
+        for external in [
+            "https://example.com/",
+            "https://svc.internal/",
+            "https://evilsvc.internal/",
+        ] {
+            assert!(elevated.forward_header(&url(external)).is_none());
+        }
+
+        // Tokens are replaced by a signed value.
+        let elevated = debug_request("allowed");
+        let (_, value) = elevated.forward_header(&url("http://127.0.0.1/")).unwrap();
+        assert_ne!(value, "allowed");
+        assert!(requests.verify_signed(value.to_str().unwrap()));
+
+        // And not forwarded at all without a signing key.
+        let unsigned = DebugRequests::new(&settings::DebugRequest {
+            tokens: vec!["allowed".to_string()],
+            forward_hosts: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(unsigned
+            .forward_value(&HeaderValue::from_static("allowed"))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn forwards_header_to_internal_client_requests() {
+        use crate::middleware::logging::Logger;
+        use wiremock::{
+            matchers::{header_exists, method},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header_exists("x-debug-request"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("debug"))
+            .mount(&server)
+            .await;
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(Logger)
+            .build();
+        let requests = debug_requests();
+        let debug_request = DebugRequest {
+            level: LevelFilter::DEBUG,
+            header: requests.header.clone(),
+            forward: requests.forward_value(&HeaderValue::from_static("allowed")),
+            forward_hosts: requests.forward_hosts.clone(),
+        };
+
+        let res = debug_request
+            .clone()
+            .scope(client.get(server.uri()).send())
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "debug");
+
+        // Not forwarded to hosts outside of the allowlist, here the same
+        // server by another name.
+        let external = server.uri().replace("127.0.0.1", "localhost");
+        let res = debug_request
+            .scope(client.get(&external).send())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+
+        // Nor outside of debug requests.
+        let res = client.get(server.uri()).send().await.unwrap();
+        assert_eq!(res.status(), 404);
+
+        let forwarded: Vec<_> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| {
+                request
+                    .headers
+                    .keys()
+                    .any(|name| name.as_str() == "x-debug-request")
+            })
+            .collect();
+        assert_eq!(forwarded, [true, false, false]);
+    }
+}
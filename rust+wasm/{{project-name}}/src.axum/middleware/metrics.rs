@@ -1,15 +1,43 @@
 //! Middleware for tracking metrics on each [axum::http::Request].
 
-use crate::middleware::request_ext::RequestExt;
-use axum::{body::Body, http::Request, middleware::Next, response::IntoResponse};
+use crate::metrics::{
+    cardinality::{self, UNMATCHED_PATH},
+    registry::MetricDescription,
+};
+use axum::{
+    body::Body, extract::MatchedPath, http::Request, middleware::Next, response::IntoResponse,
+};
+use metrics::Unit;
 use std::time::Instant;
 
+/// Descriptions of metrics recorded by [track].
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::counter("http_requests_total", "HTTP requests handled.")
+            .with_unit(Unit::Count),
+        MetricDescription::histogram(
+            "http_request_duration_seconds",
+            "HTTP request handling duration in seconds.",
+        )
+        .with_unit(Unit::Seconds),
+    ]
+}
+
 /// Middleware function called to track (and update) http metrics when a route
 /// is requested.
+///
+/// Requests are labeled by their matched route, or [UNMATCHED_PATH] if none
+/// matched, with the number of distinct paths capped by the
+/// [CardinalityGuard](cardinality::CardinalityGuard).
 pub async fn track(req: Request<Body>, next: Next) -> impl IntoResponse {
     let start = Instant::now();
 
-    let path = req.path().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_PATH.to_string());
+    let path = cardinality::guard().label("http_requests_total", "request_path", path);
 
     let res = next.run(req).await;
     let latency = start.elapsed().as_secs_f64();
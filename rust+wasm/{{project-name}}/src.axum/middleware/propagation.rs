@@ -0,0 +1,169 @@
+//! Middleware logging allowed [W3C baggage] entries of each
+//! [axum::http::Request].
+//!
+//! [W3C baggage]: <https://www.w3.org/TR/baggage/>
+
+use crate::{settings::LoggedBaggage, tracing_layers::storage_layer};
+use axum::{body::Body, extract::State, http::Request, middleware::Next, response::IntoResponse};
+use opentelemetry::baggage::BaggageExt;
+use std::sync::Arc;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Prefix for span fields holding baggage entries.
+const BAGGAGE_PREFIX: &str = "baggage.";
+
+/// Middleware function adding the request's baggage entries listed in
+/// [LoggedBaggage] to the request span's storage, as `baggage.<key>` fields,
+/// for logging. Values are truncated to its `max_value_length`.
+///
+/// Must run within the request span, once the incoming trace context and
+/// baggage are extracted with the global (configured) propagator, e.g. by
+/// [OtelAxumLayer](axum_tracing_opentelemetry::middleware::OtelAxumLayer),
+/// which carries them on to spans created within the request, and so to
+/// outgoing [reqwest] requests.
+pub async fn log_baggage(
+    State(settings): State<Arc<LoggedBaggage>>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    if !settings.keys.is_empty() {
+        let span = Span::current();
+        let cx = span.context();
+        storage_layer::insert_fields(
+            &span,
+            cx.baggage()
+                .iter()
+                .filter(|(key, _)| settings.keys.iter().any(|logged| logged == key.as_str()))
+                .map(|(key, (value, _))| {
+                    (
+                        format!("{BAGGAGE_PREFIX}{key}"),
+                        truncate(&value.as_str(), settings.max_value_length).to_string(),
+                    )
+                }),
+        );
+    }
+
+    next.run(req).await
+}
+
+/// `value` truncated to at most `max_length` bytes, on a char boundary.
+fn truncate(value: &str, max_length: usize) -> &str {
+    let mut end = max_length.min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        propagation::{composite_propagator, HeaderExtractor},
+        settings::Propagator,
+        tracing_layers::storage_layer::StorageLayer,
+    };
+    use axum::{routing::get, Router};
+    use opentelemetry::{propagation::TextMapPropagator, trace::TracerProvider as _};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tower::ServiceExt;
+    use tracing::Instrument;
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    /// Layer capturing the stored `baggage.*` fields seen by events.
+    #[derive(Clone, Default)]
+    struct BaggageFields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl<S> Layer<S> for BaggageFields
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        fn on_event(&self, _event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let Some(span) = ctx.lookup_current() else {
+                return;
+            };
+            let extensions = span.extensions();
+            if let Some(storage) = extensions.get::<storage_layer::Storage<'_>>() {
+                self.0.lock().unwrap().extend(
+                    storage
+                        .values()
+                        .iter()
+                        .filter(|(k, _)| k.starts_with(BAGGAGE_PREFIX))
+                        .map(|(k, v)| (k.to_string(), v.to_string())),
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_allowed_baggage_and_forwards_context() {
+        let propagator = Arc::new(composite_propagator(&[Propagator::B3, Propagator::Baggage]));
+
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let fields = BaggageFields::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default())
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(fields.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let outgoing = Arc::new(Mutex::new(HashMap::new()));
+        let headers = outgoing.clone();
+        let inject = propagator.clone();
+        let settings = LoggedBaggage {
+            keys: vec!["tenant".to_string(), "region".to_string()],
+            max_value_length: 4,
+        };
+        let app = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    tracing::info!("handled");
+                    let cx = tracing::info_span!("client").context();
+                    inject.inject_context(&cx, &mut *headers.lock().unwrap());
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(settings),
+                log_baggage,
+            ));
+
+        let request = Request::builder()
+            .uri("/")
+            .header("b3", format!("{TRACE_ID}-00f067aa0ba902b7-1"))
+            .header("baggage", "tenant=acme,region=eu%20west,secret=hunter2")
+            .body(Body::empty())
+            .unwrap();
+        // Extracted into the request span, as by `OtelAxumLayer`.
+        let span = tracing::info_span!("request");
+        span.set_parent(propagator.extract(&HeaderExtractor(request.headers())));
+        app.oneshot(request).instrument(span).await.unwrap();
+
+        let mut fields = fields.0.lock().unwrap().clone();
+        fields.sort();
+        assert_eq!(
+            fields,
+            vec![
+                ("baggage.region".to_string(), "eu w".to_string()),
+                ("baggage.tenant".to_string(), "acme".to_string()),
+            ]
+        );
+
+        let outgoing = outgoing.lock().unwrap();
+        assert!(outgoing["b3"].starts_with(TRACE_ID));
+        assert!(outgoing["baggage"].contains("tenant=acme"));
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        assert_eq!(truncate("acme", 128), "acme");
+        assert_eq!(truncate("acme", 2), "ac");
+        assert_eq!(truncate("zürich", 2), "z");
+    }
+}
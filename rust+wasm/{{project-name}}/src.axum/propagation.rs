@@ -0,0 +1,361 @@
+//! Trace context propagation formats, combined into a composite propagator
+//! from [Otel](crate::settings::Otel) settings.
+//!
+//! Besides the [W3C trace context] and [W3C baggage] propagators provided by
+//! [opentelemetry_sdk], this includes [B3] (single and multiple header) and
+//! [Jaeger] propagators, for services that still speak those formats.
+//!
+//! [W3C trace context]: <https://www.w3.org/TR/trace-context/>
+//! [W3C baggage]: <https://www.w3.org/TR/baggage/>
+//! [B3]: <https://github.com/openzipkin/b3-propagation>
+//! [Jaeger]: <https://www.jaegertracing.io/docs/1.57/client-libraries/#propagation-format>
+
+use crate::settings::Propagator;
+use http::HeaderMap;
+use opentelemetry::{
+    propagation::{
+        text_map_propagator::FieldIter, Extractor, Injector, TextMapCompositePropagator,
+        TextMapPropagator,
+    },
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+
+const B3_SINGLE_HEADER: &str = "b3";
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+const JAEGER_HEADER: &str = "uber-trace-id";
+
+/// [Extractor] for http request headers.
+#[derive(Debug)]
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Build a composite propagator, injecting and extracting each of the given
+/// formats in order.
+pub fn composite_propagator(propagators: &[Propagator]) -> TextMapCompositePropagator {
+    let propagators = propagators
+        .iter()
+        .map(|propagator| -> Box<dyn TextMapPropagator + Send + Sync> {
+            match propagator {
+                Propagator::TraceContext => Box::new(TraceContextPropagator::new()),
+                Propagator::Baggage => Box::new(BaggagePropagator::new()),
+                Propagator::B3 => Box::new(B3Propagator::single_header()),
+                Propagator::B3Multi => Box::new(B3Propagator::multiple_headers()),
+                Propagator::Jaeger => Box::new(JaegerPropagator::new()),
+            }
+        })
+        .collect();
+
+    TextMapCompositePropagator::new(propagators)
+}
+
+/// [B3] propagator, injecting either the single `b3` header or the
+/// `X-B3-*` headers. Both encodings are accepted on extraction.
+///
+/// [B3]: <https://github.com/openzipkin/b3-propagation>
+#[derive(Clone, Debug)]
+pub struct B3Propagator {
+    single_header: bool,
+    fields: Vec<String>,
+}
+
+impl B3Propagator {
+    /// Propagator injecting the single `b3` header.
+    pub fn single_header() -> Self {
+        Self {
+            single_header: true,
+            fields: vec![B3_SINGLE_HEADER.to_string()],
+        }
+    }
+
+    /// Propagator injecting the `X-B3-TraceId`, `X-B3-SpanId` and
+    /// `X-B3-Sampled` headers.
+    pub fn multiple_headers() -> Self {
+        Self {
+            single_header: false,
+            fields: vec![
+                B3_TRACE_ID_HEADER.to_string(),
+                B3_SPAN_ID_HEADER.to_string(),
+                B3_SAMPLED_HEADER.to_string(),
+            ],
+        }
+    }
+
+    fn extract_single_header(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let header = extractor.get(B3_SINGLE_HEADER)?.trim();
+        let mut parts = header.split('-');
+
+        let trace_id = parse_trace_id(parts.next()?)?;
+        let span_id = SpanId::from_hex(parts.next()?).ok()?;
+        let sampled = match parts.next() {
+            Some(flag) => parse_b3_sampled(flag)?,
+            None => TraceFlags::SAMPLED,
+        };
+
+        span_context(trace_id, span_id, sampled)
+    }
+
+    fn extract_multiple_headers(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let trace_id = parse_trace_id(extractor.get(B3_TRACE_ID_HEADER)?.trim())?;
+        let span_id = SpanId::from_hex(extractor.get(B3_SPAN_ID_HEADER)?.trim()).ok()?;
+
+        let sampled = if extractor.get(B3_FLAGS_HEADER).map(str::trim) == Some("1") {
+            TraceFlags::SAMPLED
+        } else {
+            match extractor.get(B3_SAMPLED_HEADER) {
+                Some(flag) => parse_b3_sampled(flag.trim())?,
+                None => TraceFlags::SAMPLED,
+            }
+        };
+
+        span_context(trace_id, span_id, sampled)
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+
+        let sampled = if span_context.is_sampled() { "1" } else { "0" };
+
+        if self.single_header {
+            injector.set(
+                B3_SINGLE_HEADER,
+                format!(
+                    "{}-{}-{sampled}",
+                    span_context.trace_id(),
+                    span_context.span_id()
+                ),
+            );
+        } else {
+            injector.set(B3_TRACE_ID_HEADER, span_context.trace_id().to_string());
+            injector.set(B3_SPAN_ID_HEADER, span_context.span_id().to_string());
+            injector.set(B3_SAMPLED_HEADER, sampled.to_string());
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_single_header(extractor)
+            .or_else(|| self.extract_multiple_headers(extractor))
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+/// [Jaeger] propagator, using the `uber-trace-id` header.
+///
+/// [Jaeger]: <https://www.jaegertracing.io/docs/1.57/client-libraries/#propagation-format>
+#[derive(Clone, Debug)]
+pub struct JaegerPropagator {
+    fields: Vec<String>,
+}
+
+impl JaegerPropagator {
+    /// Create a new [JaegerPropagator].
+    pub fn new() -> Self {
+        Self {
+            fields: vec![JAEGER_HEADER.to_string()],
+        }
+    }
+}
+
+impl Default for JaegerPropagator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextMapPropagator for JaegerPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+
+        let flags = if span_context.is_sampled() { 1 } else { 0 };
+        injector.set(
+            JAEGER_HEADER,
+            format!(
+                "{}:{}:0:{flags}",
+                span_context.trace_id(),
+                span_context.span_id()
+            ),
+        );
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        extractor
+            .get(JAEGER_HEADER)
+            .and_then(|header| {
+                // Header values may be URL-encoded, e.g. `%3A` for `:`.
+                let header = header.trim().replace("%3A", ":").replace("%3a", ":");
+                let parts = header.split(':').collect::<Vec<_>>();
+                let [trace_id, span_id, _parent_span_id, flags] = parts[..] else {
+                    return None;
+                };
+
+                let trace_id = parse_trace_id(trace_id)?;
+                let span_id = SpanId::from_hex(&format!("{span_id:0>16}")).ok()?;
+                let flags = u8::from_str_radix(flags, 16).ok()?;
+                let sampled = if flags & 0x01 == 0x01 {
+                    TraceFlags::SAMPLED
+                } else {
+                    TraceFlags::default()
+                };
+
+                span_context(trace_id, span_id, sampled)
+            })
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+/// Parse a 64 or 128-bit hex trace id, left-padding 64-bit ids.
+fn parse_trace_id(trace_id: &str) -> Option<TraceId> {
+    match trace_id.len() {
+        1..=16 => TraceId::from_hex(&format!("{trace_id:0>32}")).ok(),
+        32 => TraceId::from_hex(trace_id).ok(),
+        _ => None,
+    }
+}
+
+fn parse_b3_sampled(flag: &str) -> Option<TraceFlags> {
+    match flag {
+        "1" | "d" | "true" => Some(TraceFlags::SAMPLED),
+        "0" | "false" => Some(TraceFlags::default()),
+        _ => None,
+    }
+}
+
+fn span_context(trace_id: TraceId, span_id: SpanId, flags: TraceFlags) -> Option<SpanContext> {
+    let span_context = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    span_context.is_valid().then_some(span_context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn context() -> Context {
+        Context::new().with_remote_span_context(
+            span_context(
+                TraceId::from_hex(TRACE_ID).unwrap(),
+                SpanId::from_hex(SPAN_ID).unwrap(),
+                TraceFlags::SAMPLED,
+            )
+            .unwrap(),
+        )
+    }
+
+    fn extracted(propagator: &dyn TextMapPropagator, headers: &[(&str, &str)]) -> SpanContext {
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        propagator.extract(&headers).span().span_context().clone()
+    }
+
+    #[test]
+    fn b3_round_trips() {
+        for propagator in [
+            B3Propagator::single_header(),
+            B3Propagator::multiple_headers(),
+        ] {
+            let mut headers = HashMap::new();
+            propagator.inject_context(&context(), &mut headers);
+            assert_eq!(
+                propagator.extract(&headers).span().span_context(),
+                context().span().span_context()
+            );
+        }
+
+        let mut headers = HashMap::new();
+        B3Propagator::single_header().inject_context(&context(), &mut headers);
+        assert_eq!(headers["b3"], format!("{TRACE_ID}-{SPAN_ID}-1"));
+    }
+
+    #[test]
+    fn b3_extracts_either_encoding() {
+        let propagator = B3Propagator::multiple_headers();
+
+        let single = extracted(&propagator, &[("b3", &format!("{TRACE_ID}-{SPAN_ID}-0"))]);
+        assert_eq!(single.trace_id().to_string(), TRACE_ID);
+        assert!(!single.is_sampled());
+
+        let multi = extracted(
+            &propagator,
+            &[
+                ("x-b3-traceid", "a3ce929d0e0e4736"),
+                ("x-b3-spanid", SPAN_ID),
+                ("x-b3-flags", "1"),
+            ],
+        );
+        assert_eq!(
+            multi.trace_id().to_string(),
+            "0000000000000000a3ce929d0e0e4736"
+        );
+        assert!(multi.is_sampled());
+
+        assert!(!extracted(&propagator, &[("b3", "garbage")]).is_valid());
+    }
+
+    #[test]
+    fn jaeger_round_trips() {
+        let propagator = JaegerPropagator::new();
+        let mut headers = HashMap::new();
+        propagator.inject_context(&context(), &mut headers);
+        assert_eq!(
+            headers["uber-trace-id"],
+            format!("{TRACE_ID}:{SPAN_ID}:0:1")
+        );
+
+        let span_context = extracted(
+            &propagator,
+            &[("uber-trace-id", &format!("{TRACE_ID}%3A{SPAN_ID}%3A0%3A0"))],
+        );
+        assert_eq!(span_context.span_id().to_string(), SPAN_ID);
+        assert!(!span_context.is_sampled());
+    }
+
+    #[test]
+    fn composite_injects_all_formats() {
+        let propagator =
+            composite_propagator(&[Propagator::TraceContext, Propagator::B3, Propagator::Jaeger]);
+        let mut headers = HashMap::new();
+        propagator.inject_context(&context(), &mut headers);
+
+        assert!(headers.contains_key("traceparent"));
+        assert!(headers.contains_key("b3"));
+        assert!(headers.contains_key("uber-trace-id"));
+    }
+}
@@ -0,0 +1,403 @@
+//! Admin routes, served on the metrics port: metrics, build info, effective
+//! settings and runtime log filter changes.
+
+use crate::{
+    error::{AppError, AppResult},
+    extract::json::Json,
+    middleware::admin_auth::AdminCaller,
+    tracing_layers::log_filter::LogFilter,
+};
+use axum::{
+    self,
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    Extension,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+
+/// State shared by admin routes.
+#[derive(Clone)]
+pub struct AdminState {
+    recorder: PrometheusHandle,
+    settings: Arc<serde_json::Value>,
+    log_filter: LogFilter,
+}
+
+impl AdminState {
+    /// Create a new [AdminState], serving metrics rendered by `recorder`,
+    /// `settings` as serialized (with secrets redacted), and changing the
+    /// directive of `log_filter`.
+    pub fn new(
+        recorder: PrometheusHandle,
+        settings: serde_json::Value,
+        log_filter: LogFilter,
+    ) -> Self {
+        Self {
+            recorder,
+            settings: Arc::new(settings),
+            log_filter,
+        }
+    }
+}
+
+impl fmt::Debug for AdminState {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("AdminState")
+            .field("log_filter", &self.log_filter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Build information of the running binary.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Package name.
+    pub name: String,
+    /// Package version.
+    pub version: String,
+    /// Short git commit hash, or `unknown` if built outside of git.
+    pub git_sha: String,
+    /// Version of the compiler used for the build.
+    pub rustc: String,
+    /// Enabled cargo features.
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    /// [BuildInfo] of this build.
+    pub fn current() -> Self {
+        let features = [
+            ("alloc-metrics", cfg!(feature = "alloc-metrics")),
+            ("ansi-logs", cfg!(feature = "ansi-logs")),
+            ("console", cfg!(feature = "console")),
+            ("test_utils", cfg!(feature = "test_utils")),
+        ];
+
+        Self {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: env!("GIT_SHA").to_string(),
+            rustc: env!("RUSTC_VERSION").to_string(),
+            features: features
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Log filter, as an [EnvFilter] directive.
+///
+/// [EnvFilter]: tracing_subscriber::EnvFilter
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LogLevel {
+    /// Current filter directive.
+    pub directive: String,
+    /// Directive restored by a pending revert of a temporary change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_to: Option<String>,
+    /// Time of the pending revert, in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_at: Option<String>,
+}
+
+impl From<&LogFilter> for LogLevel {
+    fn from(log_filter: &LogFilter) -> Self {
+        let revert = log_filter.pending_revert();
+        Self {
+            directive: log_filter.directive(),
+            revert_to: revert.as_ref().map(|revert| revert.directive.clone()),
+            revert_at: revert.map(|revert| revert.at.to_rfc3339()),
+        }
+    }
+}
+
+/// Requested log filter change.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetLogLevel {
+    /// [EnvFilter] directive, e.g. `debug` or `info,my_crate=trace`.
+    ///
+    /// [EnvFilter]: tracing_subscriber::EnvFilter
+    pub directive: String,
+    /// Revert to the previous directive after this many seconds. The change
+    /// is permanent if unset.
+    #[serde(default)]
+    pub revert_after_secs: Option<u64>,
+}
+
+/// GET handler rendering metrics in the Prometheus exposition format.
+pub async fn metrics(State(state): State<AdminState>) -> String {
+    state.recorder.render()
+}
+
+/// GET handler for build information.
+pub async fn build_info() -> Json<BuildInfo> {
+    Json(BuildInfo::current())
+}
+
+/// GET handler for the effective settings, with secrets redacted.
+pub async fn settings(State(state): State<AdminState>) -> Json<serde_json::Value> {
+    Json(state.settings.as_ref().clone())
+}
+
+/// GET handler for the current log filter.
+pub async fn log_level(State(state): State<AdminState>) -> Json<LogLevel> {
+    Json(LogLevel::from(&state.log_filter))
+}
+
+/// PUT handler changing the log filter, optionally for a limited time.
+pub async fn set_log_level(
+    State(state): State<AdminState>,
+    caller: Option<Extension<AdminCaller>>,
+    remote_addr: Option<ConnectInfo<SocketAddr>>,
+    Json(body): Json<SetLogLevel>,
+) -> AppResult<Json<LogLevel>> {
+    let caller = caller.map(|Extension(caller)| caller).unwrap_or_default();
+    let previous = state.log_filter.directive();
+
+    match body.revert_after_secs {
+        Some(secs) => state
+            .log_filter
+            .set_directive_for(&body.directive, Duration::from_secs(secs)),
+        None => state.log_filter.set_directive(&body.directive),
+    }
+    .map_err(|err| AppError::new(StatusCode::BAD_REQUEST, Some(err)))?;
+
+    tracing::warn!(
+        subject = "admin",
+        category = "log_filter",
+        caller = %caller,
+        remote_addr = remote_addr.map(|ConnectInfo(addr)| addr.to_string()),
+        directive = body.directive,
+        previous,
+        revert_after_secs = body.revert_after_secs,
+        "log filter changed"
+    );
+
+    Ok(Json(LogLevel::from(&state.log_filter)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        router::setup_admin_router,
+        settings::{AdminAuth, Settings},
+        test_utils::TestSubscriber,
+    };
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Method, Request},
+        Router,
+    };
+    use config::{Config, File, FileFormat};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use serde::de::DeserializeOwned;
+    use tower::ServiceExt;
+
+    /// Default settings, with every kind of secret set.
+    fn settings() -> Settings {
+        Config::builder()
+            .add_source(File::with_name(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/config/settings.toml"
+            )))
+            .add_source(File::from_str(
+                r#"
+                [admin]
+                auth = { type = "bearer", token = "admin-token" }
+
+                [logging.debug_request]
+                tokens = ["debug-token"]
+                signing_key = "debug-signing-key"
+
+                [otel.headers]
+                x-api-key = "otlp-api-key"
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn app(auth: Option<AdminAuth>) -> (Router, LogFilter) {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let log_filter = LogFilter::new("info").unwrap();
+        let state = AdminState::new(
+            recorder.handle(),
+            serde_json::to_value(settings()).unwrap(),
+            log_filter.clone(),
+        );
+        (setup_admin_router(state, auth), log_filter)
+    }
+
+    fn bearer() -> Option<AdminAuth> {
+        Some(AdminAuth::Bearer {
+            token: "secret".to_string(),
+        })
+    }
+
+    async fn json<T: DeserializeOwned>(response: axum::response::Response) -> T {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_admin_routes() {
+        let (app, _) = app(bearer());
+
+        let res = app.clone().oneshot(get("/metrics")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.clone().oneshot(get("/build-info")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let info: BuildInfo = json(res).await;
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        assert!(info.rustc.starts_with("rustc"));
+
+        let res = app.clone().oneshot(get("/settings")).await.unwrap();
+        let settings: serde_json::Value = json(res).await;
+        assert_eq!(settings["admin"]["auth"]["type"], "bearer");
+        assert_eq!(settings["admin"]["auth"]["token"], "<redacted>");
+        let debug_request = &settings["logging"]["debug_request"];
+        assert_eq!(debug_request["tokens"][0], "<redacted>");
+        assert_eq!(debug_request["signing_key"], "<redacted>");
+        assert_eq!(settings["otel"]["headers"]["x-api-key"], "<redacted>");
+        let rendered = settings.to_string();
+        for secret in [
+            "admin-token",
+            "debug-token",
+            "debug-signing-key",
+            "otlp-api-key",
+        ] {
+            assert!(!rendered.contains(secret), "{secret} not redacted");
+        }
+
+        let res = app.clone().oneshot(get("/missing")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // Every route, including the fallback, requires credentials.
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .uri("/missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn changes_log_level() {
+        let (app, log_filter) = app(bearer());
+
+        let res = app.clone().oneshot(get("/log-level")).await.unwrap();
+        let level: LogLevel = json(res).await;
+        assert_eq!(level.directive, "info");
+        assert_eq!(level.revert_at, None);
+
+        let put = |body: &str| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri("/log-level")
+                .header(AUTHORIZATION, "Bearer secret")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(put(r#"{"directive": "info,my_crate=debug"}"#))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(log_filter.directive(), "info,my_crate=debug");
+
+        let res = app
+            .clone()
+            .oneshot(put(r#"{"directive": "trace", "revert_after_secs": 600}"#))
+            .await
+            .unwrap();
+        let level: LogLevel = json(res).await;
+        assert_eq!(level.directive, "trace");
+        assert_eq!(level.revert_to.as_deref(), Some("info,my_crate=debug"));
+        assert!(level.revert_at.is_some());
+
+        let res = app
+            .oneshot(put(r#"{"directive": "info,["}"#))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(log_filter.directive(), "trace");
+    }
+
+    #[tokio::test]
+    async fn disables_log_level_changes_without_auth() {
+        let (app, log_filter) = app(None);
+
+        let res = app.clone().oneshot(get("/log-level")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::PUT)
+                    .uri("/log-level")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"directive": "trace"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(log_filter.directive(), "info");
+    }
+
+    #[tokio::test]
+    async fn logs_log_level_changes_with_caller() {
+        let capture = TestSubscriber::new();
+        let _guard = capture.set_default();
+        let (app, _) = app(Some(AdminAuth::Basic {
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+        }));
+
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::PUT)
+                    .uri("/log-level")
+                    .header(AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"directive": "debug"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let lines = capture.lines_containing(&["log filter changed"]);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("caller=user:admin"));
+        assert!(lines[0].contains("previous=info"));
+    }
+}
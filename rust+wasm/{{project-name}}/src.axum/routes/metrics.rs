@@ -0,0 +1,10 @@
+//! Metrics catalog route, served alongside `/metrics`.
+
+use crate::metrics::registry::{self, MetricDescription};
+use axum::{self, Json};
+
+/// GET handler listing all registered metrics, with their kind, unit,
+/// description and custom histogram buckets.
+pub async fn catalog() -> Json<Vec<MetricDescription>> {
+    Json(registry::catalog())
+}
@@ -0,0 +1,323 @@
+//! Thread-local [metrics] recorder for asserting on metrics in tests.
+
+use metrics::{
+    set_default_local_recorder, Counter, Gauge, Histogram, Key, KeyName, LocalRecorderGuard,
+    Metadata, Recorder, SharedString, Unit,
+};
+use metrics_util::{
+    debugging::{DebugValue, DebuggingRecorder, Snapshotter},
+    CompositeKey, MetricKind,
+};
+use std::{cell::RefCell, collections::HashMap, fmt};
+
+thread_local! {
+    /// Metrics captured by the [LocalRecorder] installed on the current
+    /// thread, used by the `assert_*!` macros.
+    static CURRENT: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+/// Recorder set as the default of threads with a [LocalRecorder], which
+/// forwards to the thread's own [DebuggingRecorder], so that the default can
+/// be set by a `'static` reference without leaking a recorder per test.
+static CURRENT_RECORDER: CurrentRecorder = CurrentRecorder;
+
+/// [DebuggingRecorder] set as the [metrics] recorder for the current thread
+/// only, so that metrics can be asserted on in parallel tests, without a
+/// global recorder.
+///
+/// Async tests need to run on a current-thread runtime (the
+/// `#[tokio::test]` default) for metrics recorded by tasks to be captured.
+///
+/// ```ignore
+/// let _recorder = LocalRecorder::install();
+///
+/// metrics::counter!("http_requests_total", "method" => "GET").increment(1);
+///
+/// assert_counter!("http_requests_total", &[("method", "GET")], 1);
+/// ```
+pub struct LocalRecorder {
+    _guard: LocalRecorderGuard<'static>,
+}
+
+impl LocalRecorder {
+    /// Install a new recorder for the current thread, until dropped.
+    pub fn install() -> Self {
+        let recorder = DebuggingRecorder::new();
+        CURRENT.with(|current| {
+            *current.borrow_mut() = Some(Captured {
+                snapshotter: recorder.snapshotter(),
+                recorder,
+                histograms: HashMap::new(),
+            })
+        });
+
+        Self {
+            _guard: set_default_local_recorder(&CURRENT_RECORDER),
+        }
+    }
+
+    /// Value of the counter `name` with exactly the given `labels`.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        counter(name, labels)
+    }
+
+    /// Value of the gauge `name` with exactly the given `labels`.
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        gauge(name, labels)
+    }
+
+    /// Values recorded so far by the histogram `name`, with exactly the given
+    /// `labels`.
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Option<Vec<f64>> {
+        histogram(name, labels)
+    }
+}
+
+impl fmt::Debug for LocalRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalRecorder").finish_non_exhaustive()
+    }
+}
+
+impl Drop for LocalRecorder {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+    }
+}
+
+/// Recorder and snapshotter of a [LocalRecorder], along with histogram
+/// values taken by previous snapshots (which drain them).
+struct Captured {
+    recorder: DebuggingRecorder,
+    snapshotter: Snapshotter,
+    histograms: HashMap<CompositeKey, Vec<f64>>,
+}
+
+/// [Recorder] forwarding to the current thread's [DebuggingRecorder], if a
+/// [LocalRecorder] is installed.
+#[derive(Debug)]
+struct CurrentRecorder;
+
+impl CurrentRecorder {
+    fn with<T>(&self, f: impl FnOnce(&DebuggingRecorder) -> T) -> Option<T> {
+        CURRENT
+            .try_with(|current| {
+                current
+                    .borrow()
+                    .as_ref()
+                    .map(|captured| f(&captured.recorder))
+            })
+            .ok()
+            .flatten()
+    }
+}
+
+impl Recorder for CurrentRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.with(|recorder| recorder.describe_counter(key, unit, description));
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.with(|recorder| recorder.describe_gauge(key, unit, description));
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.with(|recorder| recorder.describe_histogram(key, unit, description));
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        self.with(|recorder| recorder.register_counter(key, metadata))
+            .unwrap_or_else(Counter::noop)
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        self.with(|recorder| recorder.register_gauge(key, metadata))
+            .unwrap_or_else(Gauge::noop)
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        self.with(|recorder| recorder.register_histogram(key, metadata))
+            .unwrap_or_else(Histogram::noop)
+    }
+}
+
+impl Captured {
+    fn find(
+        &mut self,
+        kind: MetricKind,
+        name: &str,
+        labels: &[(&str, &str)],
+    ) -> Option<DebugValue> {
+        let mut found = None;
+        for (key, _, _, value) in self.snapshotter.snapshot().into_vec() {
+            let value = match value {
+                DebugValue::Histogram(values) => {
+                    let recorded = self.histograms.entry(key.clone()).or_default();
+                    recorded.extend(values.into_iter().map(|value| value.into_inner()));
+                    DebugValue::Histogram(recorded.iter().map(|value| (*value).into()).collect())
+                }
+                value => value,
+            };
+
+            if matches(&key, kind, name, labels) {
+                found = Some(value);
+            }
+        }
+        found
+    }
+}
+
+/// Find a metric captured by the current thread's [LocalRecorder].
+///
+/// # Panics
+///
+/// Panics if no [LocalRecorder] is installed.
+fn find(kind: MetricKind, name: &str, labels: &[(&str, &str)]) -> Option<DebugValue> {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .as_mut()
+            .expect("no LocalRecorder installed on this thread")
+            .find(kind, name, labels)
+    })
+}
+
+#[doc(hidden)]
+pub fn counter(name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+    match find(MetricKind::Counter, name, labels)? {
+        DebugValue::Counter(value) => Some(value),
+        _ => None,
+    }
+}
+
+#[doc(hidden)]
+pub fn gauge(name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    match find(MetricKind::Gauge, name, labels)? {
+        DebugValue::Gauge(value) => Some(value.into_inner()),
+        _ => None,
+    }
+}
+
+#[doc(hidden)]
+pub fn histogram(name: &str, labels: &[(&str, &str)]) -> Option<Vec<f64>> {
+    match find(MetricKind::Histogram, name, labels)? {
+        DebugValue::Histogram(values) => {
+            Some(values.into_iter().map(|value| value.into_inner()).collect())
+        }
+        _ => None,
+    }
+}
+
+fn matches(key: &CompositeKey, kind: MetricKind, name: &str, labels: &[(&str, &str)]) -> bool {
+    if key.kind() != kind || key.key().name() != name {
+        return false;
+    }
+
+    let mut actual = key
+        .key()
+        .labels()
+        .map(|label| (label.key(), label.value()))
+        .collect::<Vec<_>>();
+    actual.sort_unstable();
+
+    let mut expected = labels.to_vec();
+    expected.sort_unstable();
+
+    actual == expected
+}
+
+/// Assert the value of a counter captured by the current thread's
+/// [LocalRecorder](crate::test_utils::LocalRecorder).
+///
+/// ```ignore
+/// assert_counter!("http_requests_total", &[("method", "GET")], 1);
+/// ```
+#[macro_export]
+macro_rules! assert_counter {
+    ($name:expr, $labels:expr, $expected:expr $(,)?) => {{
+        let labels: &[(&str, &str)] = $labels;
+        assert_eq!(
+            $crate::test_utils::recorder::counter($name, labels),
+            Some($expected),
+            "counter {} with labels {:?}",
+            $name,
+            labels,
+        )
+    }};
+}
+
+/// Assert the value of a gauge captured by the current thread's
+/// [LocalRecorder](crate::test_utils::LocalRecorder).
+#[macro_export]
+macro_rules! assert_gauge {
+    ($name:expr, $labels:expr, $expected:expr $(,)?) => {{
+        let labels: &[(&str, &str)] = $labels;
+        assert_eq!(
+            $crate::test_utils::recorder::gauge($name, labels),
+            Some($expected),
+            "gauge {} with labels {:?}",
+            $name,
+            labels,
+        )
+    }};
+}
+
+/// Assert the number of values recorded by a histogram captured by the
+/// current thread's [LocalRecorder](crate::test_utils::LocalRecorder).
+#[macro_export]
+macro_rules! assert_histogram_count {
+    ($name:expr, $labels:expr, $expected:expr $(,)?) => {{
+        let labels: &[(&str, &str)] = $labels;
+        assert_eq!(
+            $crate::test_utils::recorder::histogram($name, labels).map(|values| values.len()),
+            Some($expected),
+            "histogram {} with labels {:?}",
+            $name,
+            labels,
+        )
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestSubscriber;
+
+    #[test]
+    fn asserts_on_local_metrics() {
+        let recorder = LocalRecorder::install();
+
+        metrics::counter!("jobs_total", "queue" => "default").increment(2);
+        metrics::gauge!("queue_depth").set(3.0);
+        metrics::histogram!("job_duration_seconds").record(0.5);
+
+        assert_counter!("jobs_total", &[("queue", "default")], 2);
+        assert_gauge!("queue_depth", &[], 3.0);
+        assert_histogram_count!("job_duration_seconds", &[], 1);
+        metrics::histogram!("job_duration_seconds").record(1.5);
+        assert_eq!(
+            recorder.histogram("job_duration_seconds", &[]),
+            Some(vec![0.5, 1.5])
+        );
+        assert_eq!(recorder.counter("jobs_total", &[]), None);
+    }
+
+    #[test]
+    fn captures_metrics_layer_spans() {
+        let _recorder = LocalRecorder::install();
+        let capture = TestSubscriber::new();
+        let _guard = capture.set_default();
+
+        tracing::info_span!("record.fetch", metric_label_source = "cache").in_scope(|| {});
+
+        assert_counter!(
+            "fetch_total",
+            &[
+                ("result", "ok"),
+                ("source", "cache"),
+                ("span_name", "fetch")
+            ],
+            1
+        );
+    }
+}
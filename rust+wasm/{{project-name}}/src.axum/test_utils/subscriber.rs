@@ -0,0 +1,265 @@
+//! Scoped [tracing] subscriber capturing formatted log lines and span trees.
+
+use crate::{
+    settings::LogFormat,
+    tracing_layers::{
+        format_layer::LogFmtLayer, metrics_layer::MetricsLayer, storage_layer::StorageLayer,
+    },
+};
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt, io, sync::Arc};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    subscriber::DefaultGuard,
+    Subscriber,
+};
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::Context, prelude::*, registry::LookupSpan, Layer,
+};
+
+/// Subscriber for tests, made up of the [StorageLayer], [LogFmtLayer] and
+/// [MetricsLayer], capturing log lines and span trees instead of writing to
+/// stdout.
+///
+/// The subscriber is only set for the current thread, while the returned
+/// guard is held, so tests can run in parallel:
+///
+/// ```ignore
+/// let capture = TestSubscriber::new();
+/// let _guard = capture.set_default();
+///
+/// tracing::info_span!("request").in_scope(|| tracing::info!("hello"));
+///
+/// assert!(capture.lines().iter().any(|line| line.contains("msg=hello")));
+/// assert_eq!(capture.span_trees()[0].name, "request");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TestSubscriber {
+    format: LogFormat,
+    writer: CaptureWriter,
+    spans: SpanTreeLayer,
+}
+
+impl TestSubscriber {
+    /// Create a new [TestSubscriber], capturing logfmt'ed lines.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture log lines in the given [LogFormat].
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the subscriber as the default for the current thread, until the
+    /// returned guard is dropped.
+    pub fn set_default(&self) -> DefaultGuard {
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default())
+            .with(
+                LogFmtLayer::new(self.writer.clone())
+                    .with_target(true)
+                    .with_format(self.format),
+            )
+            .with(MetricsLayer)
+            .with(self.spans.clone());
+
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    /// Formatted log lines captured so far.
+    pub fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.writer.0.lock())
+            .lines()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Captured log lines containing all of the given `patterns`.
+    pub fn lines_containing(&self, patterns: &[&str]) -> Vec<String> {
+        self.lines()
+            .into_iter()
+            .filter(|line| patterns.iter().all(|pattern| line.contains(pattern)))
+            .collect()
+    }
+
+    /// Trees of spans created so far, one per root span, in creation order.
+    pub fn span_trees(&self) -> Vec<SpanNode> {
+        self.spans.trees()
+    }
+}
+
+/// A captured span, with its recorded fields and child spans.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpanNode {
+    /// Span name.
+    pub name: String,
+    /// Span fields, with values formatted as strings.
+    pub fields: BTreeMap<String, String>,
+    /// Child spans, in creation order.
+    pub children: Vec<SpanNode>,
+}
+
+impl SpanNode {
+    /// Find the first span named `name` in this tree, depth-first.
+    pub fn find(&self, name: &str) -> Option<&SpanNode> {
+        if self.name == name {
+            return Some(self);
+        }
+
+        self.children.iter().find_map(|child| child.find(name))
+    }
+}
+
+/// [MakeWriter] appending to a shared buffer.
+#[derive(Clone, Debug, Default)]
+struct CaptureWriter(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CaptureWriter {
+    type Writer = CaptureWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Span recorded by the [SpanTreeLayer].
+#[derive(Debug)]
+struct RecordedSpan {
+    parent: Option<usize>,
+    node: SpanNode,
+}
+
+/// Index of a span within [SpanTreeLayer], kept in span extensions (as span
+/// ids may be reused once closed).
+#[derive(Clone, Copy, Debug)]
+struct SpanIndex(usize);
+
+/// [Layer] recording spans, their fields and parents.
+#[derive(Clone, Debug, Default)]
+struct SpanTreeLayer {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+}
+
+impl SpanTreeLayer {
+    fn trees(&self) -> Vec<SpanNode> {
+        let spans = self.spans.lock();
+
+        fn build(spans: &[RecordedSpan], index: usize) -> SpanNode {
+            let mut node = spans[index].node.clone();
+            node.children = spans
+                .iter()
+                .enumerate()
+                .filter(|(_, span)| span.parent == Some(index))
+                .map(|(child, _)| build(spans, child))
+                .collect();
+            node
+        }
+
+        spans
+            .iter()
+            .enumerate()
+            .filter(|(_, span)| span.parent.is_none())
+            .map(|(index, _)| build(&spans, index))
+            .collect()
+    }
+}
+
+impl<S> Layer<S> for SpanTreeLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<SpanIndex>().copied())
+            .map(|SpanIndex(index)| index);
+
+        let mut visitor = FieldsVisitor::default();
+        attrs.record(&mut visitor);
+
+        let mut spans = self.spans.lock();
+        span.extensions_mut().insert(SpanIndex(spans.len()));
+        spans.push(RecordedSpan {
+            parent,
+            node: SpanNode {
+                name: span.name().to_string(),
+                fields: visitor.0,
+                children: vec![],
+            },
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+        let Some(SpanIndex(index)) = span.extensions().get::<SpanIndex>().copied() else {
+            return;
+        };
+
+        let mut visitor = FieldsVisitor::default();
+        values.record(&mut visitor);
+        self.spans.lock()[index].node.fields.extend(visitor.0);
+    }
+}
+
+#[derive(Debug, Default)]
+struct FieldsVisitor(BTreeMap<String, String>);
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_lines_and_span_trees() {
+        let capture = TestSubscriber::new();
+        let _guard = capture.set_default();
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = "abc",
+            status = tracing::field::Empty
+        );
+        span.in_scope(|| {
+            tracing::info_span!("db", table = "users").in_scope(|| {
+                tracing::info!(subject = "query", "fetched rows");
+            });
+        });
+        span.record("status", 200);
+        drop(span);
+
+        let lines = capture.lines_containing(&["msg=\"fetched rows\"", "request_id=abc"]);
+        assert_eq!(lines.len(), 1, "{:?}", capture.lines());
+
+        let trees = capture.span_trees();
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].name, "request");
+        assert_eq!(trees[0].fields["request_id"], "abc");
+        assert_eq!(trees[0].fields["status"], "200");
+        assert_eq!(trees[0].find("db").unwrap().fields["table"], "users");
+    }
+}
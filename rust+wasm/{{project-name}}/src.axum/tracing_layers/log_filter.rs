@@ -0,0 +1,522 @@
+//! Runtime-reloadable [EnvFilter] for log output layers.
+//!
+//! A [LogFilter] hands out [reload] filters for each layer it's applied to,
+//! and swaps all of them when its directive changes, e.g. from the admin
+//! server's `/log-level` endpoint. Changes can be temporary, reverting to the
+//! previous directive after a while, so a raised level isn't forgotten.
+//!
+//! Filters also enable spans and events up to the elevated level of a
+//! [debug request](crate::middleware::debug_request), within its scope, for
+//! the targets the directive enables (see [elevate]).
+
+use crate::middleware::debug_request;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+use tracing::{
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    subscriber::Interest,
+    Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{Directive, ParseError},
+    layer::{Context, Filter},
+    reload, EnvFilter,
+};
+
+type Reload = Box<dyn Fn(&str) -> Result<(), reload::Error> + Send + Sync>;
+
+/// Pending revert of a temporary directive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Revert {
+    /// Directive restored on revert.
+    pub directive: String,
+    /// Time of the revert.
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct State {
+    directive: String,
+    /// Incremented on every change, so reverts of superseded changes are
+    /// skipped.
+    generation: u64,
+    revert: Option<Revert>,
+}
+
+/// Shared, reloadable log filter directive (in [EnvFilter] syntax, e.g.
+/// `info,my_crate=debug`).
+#[derive(Clone)]
+pub struct LogFilter {
+    state: Arc<RwLock<State>>,
+    reloads: Arc<RwLock<Vec<Reload>>>,
+    max_elevation: LevelFilter,
+}
+
+impl LogFilter {
+    /// Create a new [LogFilter] from an [EnvFilter] `directive`.
+    pub fn new(directive: impl Into<String>) -> Result<Self> {
+        let directive = directive.into();
+        parse(&directive)?;
+        Ok(Self {
+            state: Arc::new(RwLock::new(State {
+                directive,
+                generation: 0,
+                revert: None,
+            })),
+            reloads: Arc::new(RwLock::new(Vec::new())),
+            max_elevation: LevelFilter::OFF,
+        })
+    }
+
+    /// Allow debug requests to elevate filters up to `level` (off by
+    /// default), making callsites up to `level` always evaluated.
+    pub fn with_max_elevation(mut self, level: LevelFilter) -> Self {
+        self.max_elevation = level;
+        self
+    }
+
+    /// Per-layer filter applying the current directive, and following any
+    /// later changes.
+    pub fn filter<S>(&self) -> ElevatedFilter<reload::Layer<EnvFilter, S>>
+    where
+        S: Subscriber + 'static,
+    {
+        let directive = self.directive();
+        let max_level = self.max_elevation;
+        let (filter, handle) = reload::Layer::new(env_filter(&directive));
+        let (elevated, elevated_handle) =
+            reload::Layer::new(env_filter(&elevate(&directive, max_level)));
+        self.reloads.write().push(Box::new(move |directive| {
+            handle.reload(env_filter(directive))?;
+            elevated_handle.reload(env_filter(&elevate(directive, max_level)))
+        }));
+        ElevatedFilter {
+            inner: filter,
+            elevated,
+            max_level,
+        }
+    }
+
+    /// Current directive.
+    pub fn directive(&self) -> String {
+        self.state.read().directive.clone()
+    }
+
+    /// Pending revert of a temporary directive, if any.
+    pub fn pending_revert(&self) -> Option<Revert> {
+        self.state.read().revert.clone()
+    }
+
+    /// Replace the directive of all filters, failing on an invalid
+    /// directive. Cancels any pending revert.
+    pub fn set_directive(&self, directive: &str) -> Result<()> {
+        let mut state = self.state.write();
+        self.apply(&mut state, directive)?;
+        state.revert = None;
+        Ok(())
+    }
+
+    /// Replace the directive of all filters for `duration`, then revert to
+    /// the current directive (or, if already temporary, to the one restored
+    /// by the pending revert).
+    ///
+    /// Must be called within a [tokio] runtime.
+    pub fn set_directive_for(&self, directive: &str, duration: Duration) -> Result<()> {
+        let at = chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| Utc::now().checked_add_signed(duration))
+            .ok_or_else(|| anyhow!("log filter revert duration out of range"))?;
+
+        let mut state = self.state.write();
+        let restore = state.revert.as_ref().map_or_else(
+            || state.directive.clone(),
+            |revert| revert.directive.clone(),
+        );
+
+        self.apply(&mut state, directive)?;
+        state.revert = Some(Revert {
+            directive: restore.clone(),
+            at,
+        });
+
+        let generation = state.generation;
+        let log_filter = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            log_filter.revert(generation, &restore);
+        });
+
+        Ok(())
+    }
+
+    fn revert(&self, generation: u64, directive: &str) {
+        let mut state = self.state.write();
+        if state.generation != generation {
+            return;
+        }
+
+        match self.apply(&mut state, directive) {
+            Ok(()) => {
+                state.revert = None;
+                drop(state);
+                tracing::warn!(
+                    subject = "admin",
+                    category = "log_filter",
+                    directive,
+                    "log filter reverted"
+                );
+            }
+            Err(err) => {
+                drop(state);
+                tracing::error!(
+                    subject = "admin",
+                    category = "log_filter",
+                    directive,
+                    error = %err,
+                    "failed to revert log filter"
+                );
+            }
+        }
+    }
+
+    fn apply(&self, state: &mut State, directive: &str) -> Result<()> {
+        parse(directive)?;
+        for reload in self.reloads.read().iter() {
+            reload(directive)?;
+        }
+        state.directive = directive.to_string();
+        state.generation += 1;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("LogFilter")
+            .field("state", &*self.state.read())
+            .field("filters", &self.reloads.read().len())
+            .field("max_elevation", &self.max_elevation)
+            .finish()
+    }
+}
+
+/// Per-layer [Filter] enabling, besides what its inner filter enables, what
+/// its elevated filter enables up to the level of the current
+/// [debug request](crate::middleware::debug_request).
+#[derive(Debug)]
+pub struct ElevatedFilter<F> {
+    inner: F,
+    elevated: F,
+    max_level: LevelFilter,
+}
+
+impl<F> ElevatedFilter<F> {
+    fn in_elevation(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level
+            && debug_request::elevated_level().map_or(false, |level| *metadata.level() <= level)
+    }
+}
+
+impl<S, F: Filter<S>> Filter<S> for ElevatedFilter<F> {
+    fn enabled(&self, metadata: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, cx)
+            || (self.in_elevation(metadata) && self.elevated.enabled(metadata, cx))
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        let interest = self.inner.callsite_enabled(metadata);
+        if interest.is_never()
+            && *metadata.level() <= self.max_level
+            && !self.elevated.callsite_enabled(metadata).is_never()
+        {
+            // Decided per request, as debug requests come and go.
+            Interest::sometimes()
+        } else {
+            interest
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let elevated = self
+            .elevated
+            .max_level_hint()
+            .map(|hint| hint.min(self.max_level));
+        Some(self.inner.max_level_hint()?.max(elevated?))
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, cx: &Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, cx)
+            || (self.in_elevation(event.metadata()) && self.elevated.event_enabled(event, cx))
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_new_span(attrs, id, ctx.clone());
+        self.elevated.on_new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(id, values, ctx.clone());
+        self.elevated.on_record(id, values, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx.clone());
+        self.elevated.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx.clone());
+        self.elevated.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id.clone(), ctx.clone());
+        self.elevated.on_close(id, ctx)
+    }
+}
+
+/// Raise the levels of `directive` to at least `level`, for debug requests.
+///
+/// Only levels the directive sets are raised, so targets it doesn't enable
+/// (e.g. `hyper` or `h2` under `my_crate=info`) stay disabled, and targets
+/// it quiets below its default level, or turns off, (e.g. `hyper` under
+/// `info,hyper=warn`) keep their level.
+pub fn elevate(directive: &str, level: LevelFilter) -> String {
+    let directives: Vec<_> = split_directives(directive)
+        .into_iter()
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.rsplit_once('=') {
+            Some((target, target_level)) => match LevelFilter::from_str(target_level) {
+                Ok(target_level) => (Some(target), target_level),
+                Err(_) => (Some(directive), LevelFilter::TRACE),
+            },
+            None => match LevelFilter::from_str(directive) {
+                Ok(default_level) => (None, default_level),
+                // A bare target enables all of its levels.
+                Err(_) => (Some(directive), LevelFilter::TRACE),
+            },
+        })
+        .collect();
+    let default_level = directives
+        .iter()
+        .filter(|(target, _)| target.is_none())
+        .map(|(_, level)| *level)
+        .max()
+        .unwrap_or(LevelFilter::OFF);
+
+    directives
+        .into_iter()
+        .map(|(target, target_level)| match target {
+            Some(target) if target_level < default_level || target_level == LevelFilter::OFF => {
+                format!("{target}={target_level}")
+            }
+            Some(target) => format!("{target}={}", target_level.max(level)),
+            None => default_level.max(level).to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Split a comma-separated filter `directive` into its directives, keeping
+/// commas within span filters, e.g. of `my_crate[span{a=1,b=2}]=debug`, and
+/// quoted field values.
+fn split_directives(directive: &str) -> Vec<&str> {
+    let mut directives = Vec::new();
+    let (mut start, mut depth, mut quoted) = (0, 0usize, false);
+    for (i, c) in directive.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '{' if !quoted => depth += 1,
+            ']' | '}' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                directives.push(&directive[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    directives.push(&directive[start..]);
+    directives
+}
+
+fn parse(directive: &str) -> Result<EnvFilter> {
+    split_directives(directive)
+        .into_iter()
+        .filter(|directive| !directive.is_empty())
+        .try_fold(EnvFilter::default(), |filter, directive| {
+            Ok(filter.add_directive(Directive::from_str(directive)?))
+        })
+        .map_err(|err: ParseError| anyhow!("invalid log filter directive {directive:?}: {err}"))
+}
+
+/// [EnvFilter] of a `directive` already checked by [parse], or ignoring its
+/// invalid directives.
+fn env_filter(directive: &str) -> EnvFilter {
+    parse(directive).unwrap_or_else(|_| EnvFilter::new(directive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        middleware::debug_request::DebugRequests, settings::DebugRequest as DebugRequestSettings,
+    };
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+    use tracing_subscriber::{prelude::*, Layer};
+
+    #[derive(Clone, Default)]
+    struct CountingLayer(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for CountingLayer {
+        fn on_event(
+            &self,
+            _event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn reloads_filters() {
+        let log_filter = LogFilter::new("warn").unwrap();
+        let events = CountingLayer::default();
+        let subscriber =
+            tracing_subscriber::registry().with(events.clone().with_filter(log_filter.filter()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        tracing::info!("dropped");
+        tracing::warn!("kept");
+        assert_eq!(events.0.load(Ordering::Relaxed), 1);
+
+        log_filter.set_directive("info").unwrap();
+        tracing::info!("kept");
+        assert_eq!(events.0.load(Ordering::Relaxed), 2);
+        assert_eq!(log_filter.directive(), "info");
+
+        assert!(log_filter.set_directive("info,[").is_err());
+        assert_eq!(log_filter.directive(), "info");
+    }
+
+    #[test]
+    fn elevates_directives() {
+        let debug = LevelFilter::DEBUG;
+        assert_eq!(elevate("info", debug), "debug");
+        assert_eq!(elevate("trace", debug), "trace");
+        assert_eq!(
+            elevate("my_crate=info,tower_http=warn", debug),
+            "my_crate=debug,tower_http=debug"
+        );
+        assert_eq!(
+            elevate("info,hyper=warn,h2=off,my_crate", debug),
+            "debug,hyper=warn,h2=off,my_crate=trace"
+        );
+        assert_eq!(
+            elevate("my_crate[request{id=1}]=info", debug),
+            "my_crate[request{id=1}]=debug"
+        );
+        assert_eq!(
+            elevate("warn,my_crate[span{a=1,b=2}]=info,hyper=warn", debug),
+            "debug,my_crate[span{a=1,b=2}]=debug,hyper=debug"
+        );
+        assert_eq!(
+            elevate("my_crate[{name=\"a,b\"}]=info", debug),
+            "my_crate[{name=\"a,b\"}]=debug"
+        );
+        assert_eq!(elevate("info", LevelFilter::OFF), "info");
+        for directive in [
+            "info",
+            "my_crate=info,hyper=warn",
+            "info,my_crate",
+            "warn,my_crate[span{a=1,b=2}]=info",
+            "my_crate[span{a=1,b=2}]",
+        ] {
+            assert!(parse(&elevate(directive, debug)).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn elevates_debug_requests() {
+        let log_filter = LogFilter::new("info,hyper=warn")
+            .unwrap()
+            .with_max_elevation(LevelFilter::DEBUG);
+        let events = CountingLayer::default();
+        let subscriber =
+            tracing_subscriber::registry().with(events.clone().with_filter(log_filter.filter()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let debug_requests = DebugRequests::new(&DebugRequestSettings {
+            tokens: vec!["allowed".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    tracing::debug_span!("child").in_scope(|| tracing::debug!("body"));
+                    tracing::trace!("too verbose");
+                    tracing::debug!(target: "hyper", "quieted target");
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(debug_requests),
+                debug_request::elevate,
+            ));
+
+        let request = |value: &str| {
+            Request::builder()
+                .uri("/")
+                .header("x-debug-request", value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Only the "ignoring invalid debug request header" warning.
+        app.clone().oneshot(request("other")).await.unwrap();
+        assert_eq!(events.0.load(Ordering::Relaxed), 1);
+
+        // "elevating log level" info, and the debug event.
+        app.oneshot(request("allowed")).await.unwrap();
+        assert_eq!(events.0.load(Ordering::Relaxed), 3);
+
+        tracing::debug!("outside of requests");
+        assert_eq!(events.0.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn reverts_temporary_directives() {
+        let log_filter = LogFilter::new("info").unwrap();
+
+        log_filter
+            .set_directive_for("debug", Duration::from_millis(20))
+            .unwrap();
+        log_filter
+            .set_directive_for("trace", Duration::from_millis(40))
+            .unwrap();
+        assert_eq!(log_filter.directive(), "trace");
+        assert_eq!(log_filter.pending_revert().unwrap().directive, "info");
+
+        // The first revert is superseded by the second change.
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(log_filter.directive(), "trace");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(log_filter.directive(), "info");
+        assert!(log_filter.pending_revert().is_none());
+
+        // Permanent changes cancel pending reverts.
+        log_filter
+            .set_directive_for("debug", Duration::from_millis(10))
+            .unwrap();
+        log_filter.set_directive("warn").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(log_filter.directive(), "warn");
+    }
+}
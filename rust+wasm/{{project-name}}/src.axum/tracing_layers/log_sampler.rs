@@ -0,0 +1,310 @@
+//! Rate limiting and sampling of log events, bounding the log volume of
+//! [LogFmtLayer](crate::tracing_layers::format_layer::LogFmtLayer), e.g.
+//! under a hot error loop.
+//!
+//! Events are rate limited per callsite, within fixed intervals. Dropped
+//! events are counted, and reported with the callsite's first event of a
+//! later interval, or [flushed](LogSampler::flush) once their interval ends,
+//! so a flood shows up as a single `suppressed events` line, even if it
+//! stops.
+//!
+//! Debug and info events can additionally be sampled. Warnings and errors
+//! are never sampled, and neither are events of
+//! [debug requests](crate::middleware::debug_request).
+
+use crate::{middleware::debug_request, settings::LogSampling};
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{callsite::Identifier, Level, Metadata};
+
+/// Outcome of [LogSampler::sample] for an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sample {
+    /// Drop the event.
+    Drop,
+    /// Log the event, after a summary of the callsite's events suppressed
+    /// since it was last logged, if any.
+    Keep {
+        /// Number of suppressed events.
+        suppressed: u64,
+    },
+}
+
+/// Rate limiting window of a callsite.
+#[derive(Debug)]
+struct Window {
+    metadata: &'static Metadata<'static>,
+    start: Instant,
+    count: u32,
+    suppressed: u64,
+}
+
+/// Decides which log events to keep, as configured by [LogSampling]. Keeps
+/// every event by default.
+#[derive(Debug)]
+pub struct LogSampler {
+    max_events: Option<u32>,
+    interval: Duration,
+    debug_ratio: f64,
+    info_ratio: f64,
+    windows: Mutex<HashMap<Identifier, Window>>,
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        Self::new(&LogSampling::default()).expect("default log sampling is valid")
+    }
+}
+
+impl LogSampler {
+    /// Create a new [LogSampler] from [LogSampling] settings. Fails if
+    /// `max_events` or `interval_ms` is 0, or a ratio isn't between 0 and 1.
+    pub fn new(settings: &LogSampling) -> Result<Self> {
+        if settings.max_events == Some(0) {
+            return Err(anyhow!(
+                "invalid log sampling max_events: must be at least 1, or unset"
+            ));
+        }
+        if settings.interval_ms == 0 {
+            return Err(anyhow!(
+                "invalid log sampling interval_ms: must be at least 1"
+            ));
+        }
+        for (name, ratio) in [
+            ("debug_ratio", settings.debug_ratio),
+            ("info_ratio", settings.info_ratio),
+        ] {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(anyhow!(
+                    "invalid log sampling {name} of {ratio}: must be between 0 and 1"
+                ));
+            }
+        }
+
+        Ok(Self {
+            max_events: settings.max_events,
+            interval: settings.interval(),
+            debug_ratio: settings.debug_ratio,
+            info_ratio: settings.info_ratio,
+            windows: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether to keep an event of the callsite described by `metadata`.
+    pub fn sample(&self, metadata: &'static Metadata<'static>) -> Sample {
+        if !self.sampled(metadata.level()) {
+            return Sample::Drop;
+        }
+
+        let Some(max_events) = self.max_events else {
+            return Sample::Keep { suppressed: 0 };
+        };
+
+        let now = Instant::now();
+        let mut windows = self.windows.lock();
+        let window = windows.entry(metadata.callsite()).or_insert(Window {
+            metadata,
+            start: now,
+            count: 0,
+            suppressed: 0,
+        });
+
+        if now.duration_since(window.start) >= self.interval {
+            window.start = now;
+            window.count = 0;
+        }
+
+        if window.count < max_events {
+            window.count += 1;
+            Sample::Keep {
+                suppressed: std::mem::take(&mut window.suppressed),
+            }
+        } else {
+            window.suppressed += 1;
+            Sample::Drop
+        }
+    }
+
+    /// Interval at which to [flush](Self::flush) suppressed events, if rate
+    /// limiting.
+    pub fn flush_interval(&self) -> Option<Duration> {
+        self.max_events.map(|_| self.interval)
+    }
+
+    /// Take the counts of events suppressed in intervals that have ended,
+    /// with their callsite, to report floods without waiting for the
+    /// callsite's next event.
+    pub fn flush(&self) -> Vec<(&'static Metadata<'static>, u64)> {
+        let now = Instant::now();
+        self.windows
+            .lock()
+            .values_mut()
+            .filter(|window| {
+                window.suppressed > 0 && now.duration_since(window.start) >= self.interval
+            })
+            .map(|window| (window.metadata, std::mem::take(&mut window.suppressed)))
+            .collect()
+    }
+
+    fn sampled(&self, level: &Level) -> bool {
+        let ratio = match *level {
+            Level::TRACE | Level::DEBUG => self.debug_ratio,
+            Level::INFO => self.info_ratio,
+            Level::WARN | Level::ERROR => return true,
+        };
+
+        ratio >= 1.0 || debug_request::elevated_level().is_some() || rand::random::<f64>() < ratio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tracing::Subscriber;
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    /// Records the [Sample] of each event.
+    #[derive(Clone)]
+    struct SamplingLayer(Arc<LogSampler>, Arc<StdMutex<Vec<Sample>>>);
+
+    impl<S: Subscriber> Layer<S> for SamplingLayer {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            let sample = self.0.sample(event.metadata());
+            self.1.lock().unwrap().push(sample);
+        }
+    }
+
+    fn samples(settings: LogSampling, f: impl FnOnce()) -> Vec<Sample> {
+        let layer = SamplingLayer(
+            Arc::new(LogSampler::new(&settings).unwrap()),
+            Arc::new(StdMutex::new(Vec::new())),
+        );
+        let subscriber = tracing_subscriber::registry().with(layer.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let samples = layer.1.lock().unwrap().clone();
+        samples
+    }
+
+    #[test]
+    fn rate_limits_per_callsite() {
+        let samples = samples(
+            LogSampling {
+                max_events: Some(2),
+                interval_ms: 50,
+                ..Default::default()
+            },
+            || {
+                let hot_loop = || tracing::error!("hot loop");
+                (0..5).for_each(|_| hot_loop());
+                tracing::error!("other callsite");
+                std::thread::sleep(Duration::from_millis(60));
+                (0..2).for_each(|_| hot_loop());
+            },
+        );
+
+        let kept = Sample::Keep { suppressed: 0 };
+        assert_eq!(
+            samples,
+            [
+                kept,
+                kept,
+                Sample::Drop,
+                Sample::Drop,
+                Sample::Drop,
+                kept,
+                // Kept again in the first callsite's next interval.
+                Sample::Keep { suppressed: 3 },
+                kept,
+            ]
+        );
+    }
+
+    #[test]
+    fn flushes_ended_floods() {
+        let sampler = Arc::new(
+            LogSampler::new(&LogSampling {
+                max_events: Some(1),
+                interval_ms: 20,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let layer = SamplingLayer(sampler.clone(), Arc::new(StdMutex::new(Vec::new())));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            (0..3).for_each(|_| tracing::error!("flood"));
+        });
+
+        // Not before the interval ends.
+        assert!(sampler.flush().is_empty());
+
+        std::thread::sleep(Duration::from_millis(30));
+        let flushed = sampler.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].0.level(), &Level::ERROR);
+        assert_eq!(flushed[0].1, 2);
+        assert!(sampler.flush().is_empty());
+    }
+
+    #[test]
+    fn samples_debug_and_info_only() {
+        let samples = samples(
+            LogSampling {
+                debug_ratio: 0.0,
+                info_ratio: 0.0,
+                ..Default::default()
+            },
+            || {
+                tracing::debug!("dropped");
+                tracing::info!("dropped");
+                tracing::warn!("kept");
+                tracing::error!("kept");
+            },
+        );
+
+        let kept = Sample::Keep { suppressed: 0 };
+        assert_eq!(samples, [Sample::Drop, Sample::Drop, kept, kept]);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for settings in [
+            LogSampling {
+                max_events: Some(0),
+                ..Default::default()
+            },
+            LogSampling {
+                max_events: Some(10),
+                interval_ms: 0,
+                ..Default::default()
+            },
+            LogSampling {
+                debug_ratio: -0.5,
+                ..Default::default()
+            },
+            LogSampling {
+                info_ratio: 2.0,
+                ..Default::default()
+            },
+            LogSampling {
+                info_ratio: f64::NAN,
+                ..Default::default()
+            },
+        ] {
+            assert!(LogSampler::new(&settings).is_err(), "{settings:?}");
+        }
+
+        assert!(LogSampler::new(&LogSampling {
+            max_events: Some(1),
+            interval_ms: 1,
+            debug_ratio: 0.0,
+            info_ratio: 1.0,
+        })
+        .is_ok());
+    }
+}
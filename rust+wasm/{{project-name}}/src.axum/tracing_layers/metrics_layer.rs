@@ -1,6 +1,6 @@
 //! Metrics layer.
 
-use crate::tracing_layers::storage_layer::Storage;
+use crate::{metrics::cardinality, tracing_layers::storage_layer::Storage};
 use std::{borrow::Cow, time::Instant};
 use tracing::{Id, Subscriber};
 use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
@@ -16,9 +16,19 @@ const SPAN_LABEL: &str = "span_name";
 /// Prefix used for capturing metric spans/instrumentations.
 pub const METRIC_META_PREFIX: &str = "record.";
 
+/// Attribute instrumenting a function with a `record.*` span, as picked up by
+/// the [MetricsLayer].
+pub use {{crate_name}}_macros::instrument_metric;
+
 /// Metrics layer for automatically deriving metrics for record.* events.
 ///
 /// Append to custom [LogFmtLayer](crate::tracing_layers::format_layer::LogFmtLayer).
+///
+/// Distinct values of `metric_label_*` fields are capped per metric by the
+/// [CardinalityGuard](crate::metrics::cardinality::CardinalityGuard).
+///
+/// Derived metrics can be described, and given custom histogram buckets, with
+/// [register_span_metric](crate::metrics::registry::register_span_metric).
 #[derive(Debug)]
 pub struct MetricsLayer;
 
@@ -36,39 +46,43 @@ where
             .unwrap_or(0.0);
 
         if let Some(visitor) = extensions.get_mut::<Storage<'_>>() {
-            let mut labels = vec![];
-            for (key, value) in visitor.values() {
-                if key.starts_with(PREFIX_LABEL) {
-                    labels.push((
-                        key.strip_prefix(PREFIX_LABEL).unwrap_or(LABEL),
-                        value.to_string(),
-                    ))
-                }
-            }
-
             let span_name = span
                 .name()
                 .strip_prefix(METRIC_META_PREFIX)
                 .unwrap_or_else(|| span.name());
 
-            labels.push((SPAN_LABEL, span_name.to_string()));
-
             let name = visitor
                 .values()
                 .get(METRIC_NAME)
                 .unwrap_or(&Cow::from(span_name))
                 .to_string();
 
+            let mut labels = vec![];
+            for (key, value) in visitor.values() {
+                if key.starts_with(PREFIX_LABEL) {
+                    labels.push((
+                        key.strip_prefix(PREFIX_LABEL).unwrap_or(LABEL).to_string(),
+                        value.to_string(),
+                    ))
+                }
+            }
+
+            let counter = format!("{name}_total");
+
+            // Cap distinct values of user-provided labels.
+            let mut labels = cardinality::guard().labels(&counter, labels);
+            labels.push((SPAN_LABEL.to_string(), span_name.to_string()));
+
             if visitor.values().contains_key(ERROR) {
-                labels.push((RESULT_LABEL, String::from(ERROR)))
+                labels.push((RESULT_LABEL.to_string(), String::from(ERROR)))
             } else {
-                labels.push((RESULT_LABEL, String::from(OK)))
+                labels.push((RESULT_LABEL.to_string(), String::from(OK)))
             }
 
             // Need to sort labels to remain the same across all metrics.
             labels.sort_unstable();
 
-            metrics::counter!(format!("{name}_total"), &labels).increment(1);
+            metrics::counter!(counter, &labels).increment(1);
             metrics::histogram!(format!("{name}_duration_seconds"), &labels)
                 .record(elapsed_secs_f64);
 
@@ -79,3 +93,56 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_counter, assert_histogram_count,
+        test_utils::{LocalRecorder, TestSubscriber},
+    };
+    use anyhow::{anyhow, Result};
+
+    #[instrument_metric(name = "db_event", labels(event_type, source = "api"))]
+    async fn save_event(event_type: &str, fail: bool) -> Result<()> {
+        if fail {
+            return Err(anyhow!("failed to save {event_type} event"));
+        }
+        Ok(())
+    }
+
+    #[instrument_metric(level = "debug")]
+    fn compute() -> u64 {
+        42
+    }
+
+    #[tokio::test]
+    async fn derives_metrics_from_instrumented_fns() {
+        let _recorder = LocalRecorder::install();
+        let capture = TestSubscriber::new();
+        let _guard = capture.set_default();
+
+        save_event("song_added", false).await.unwrap();
+        save_event("song_added", true).await.unwrap_err();
+        assert_eq!(compute(), 42);
+
+        let labels = |result| {
+            [
+                ("event_type", "song_added"),
+                ("result", result),
+                ("source", "api"),
+                ("span_name", "save_event"),
+            ]
+        };
+        assert_counter!("db_event_total", &labels("ok"), 1);
+        assert_counter!("db_event_total", &labels("error"), 1);
+        assert_histogram_count!("db_event_duration_seconds", &labels("ok"), 1);
+        assert_counter!(
+            "compute_total",
+            &[("result", "ok"), ("span_name", "compute")],
+            1
+        );
+
+        assert_eq!(capture.span_trees()[0].name, "record.save_event");
+    }
+}
@@ -1,62 +1,209 @@
 //! Storage layer.
 
-use std::{borrow::Cow, collections::HashMap, fmt, time::Instant};
+use parking_lot::RwLock;
+use std::{borrow::Cow, collections::HashMap, fmt, sync::Arc, time::Instant};
 use tracing::{
+    dispatcher::WeakDispatch,
     field::{Field, Visit},
     span::{Attributes, Record},
-    Event, Id, Subscriber,
+    Dispatch, Event, Id, Span, Subscriber,
+};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    Layer, Registry,
 };
-use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
 
 /// Storage fields for events.
-const ON_EVENT_KEEP_FIELDS: [&str; 1] = ["error"];
+pub const ON_EVENT_KEEP_FIELDS: [&str; 1] = ["error"];
 
 const TRACE_ID: &str = "trace_id";
 const PARENT_SPAN: &str = "parent_span";
 const FOLLOWS_FROM_TRACE_ID: &str = "follows_from.trace_id";
 const FOLLOWS_FROM_FIELD: &str = "follows_from";
 const LATENCY_FIELD: &str = "latency_ms";
+const MESSAGE_FIELD: &str = "message";
 
 /// Storage layer for contextual trace information.
 ///
 /// Prepend to custom [LogFmtLayer](crate::tracing_layers::format_layer::LogFmtLayer).
 #[derive(Clone, Debug)]
-pub struct StorageLayer;
+pub struct StorageLayer {
+    event_fields: Vec<String>,
+    dispatch: Arc<RwLock<Option<WeakDispatch>>>,
+}
+
+impl Default for StorageLayer {
+    fn default() -> Self {
+        Self {
+            event_fields: ON_EVENT_KEEP_FIELDS.iter().map(|f| f.to_string()).collect(),
+            dispatch: Arc::default(),
+        }
+    }
+}
+
+impl StorageLayer {
+    /// Besides [ON_EVENT_KEEP_FIELDS], store `fields` of events on their
+    /// span, e.g. a `tenant_id` resolved within a request, so they're logged
+    /// with the span's later events and close. Other event fields, and the
+    /// event's `message`, aren't stored.
+    pub fn keep_event_fields<I, F>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        for field in fields.into_iter().map(Into::into) {
+            if !self.event_fields.contains(&field) {
+                self.event_fields.push(field);
+            }
+        }
+        self
+    }
+
+    /// A [StorageReader] of `fields`, reading the storage of spans from the
+    /// [Registry] this layer is registered with.
+    pub fn reader<I, F>(&self, fields: I) -> StorageReader
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        StorageReader {
+            dispatch: self.dispatch.clone(),
+            fields: fields.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Reads fields from the [Storage] of spans regardless of the reading layer's
+/// own filter, e.g. for a log layer to write the correlation ids of a request
+/// span it doesn't enable on the events within it.
+///
+/// Created by [StorageLayer::reader]. Reads nothing until that layer is
+/// registered.
+#[derive(Clone, Debug, Default)]
+pub struct StorageReader {
+    dispatch: Arc<RwLock<Option<WeakDispatch>>>,
+    fields: Vec<String>,
+}
+
+impl StorageReader {
+    /// The reader's fields stored on span `id`.
+    pub(crate) fn fields(&self, id: &Id) -> Vec<(String, String)> {
+        self.with_span(id, |span| {
+            let extensions = span.extensions();
+            let Some(storage) = extensions.get::<Storage<'_>>() else {
+                return Vec::new();
+            };
+            self.fields
+                .iter()
+                .filter_map(|key| {
+                    storage
+                        .values
+                        .get(key.as_str())
+                        .map(|value| (key.clone(), value.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Call `f` with span `id`, as looked up in the [Registry] without any
+    /// per-layer filter.
+    pub(crate) fn with_span<T>(
+        &self,
+        id: &Id,
+        f: impl FnOnce(SpanRef<'_, Registry>) -> T,
+    ) -> Option<T> {
+        let dispatch = self.dispatch.read().as_ref()?.upgrade()?;
+        let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+        Some(f(span))
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct Storage<'a> {
-    values: HashMap<&'a str, Cow<'a, str>>,
+    values: HashMap<Cow<'a, str>, Cow<'a, str>>,
 }
 
 impl<'a> Storage<'a> {
-    pub(crate) fn values(&self) -> &HashMap<&'a str, Cow<'a, str>> {
+    pub(crate) fn values(&self) -> &HashMap<Cow<'a, str>, Cow<'a, str>> {
         &self.values
     }
 }
 
+/// Insert fields into the [Storage] of `span`, e.g. from request middleware,
+/// so that they are logged along with the span's own fields and inherited by
+/// spans created within it.
+///
+/// Does nothing if `span` is disabled or the subscriber isn't built on a
+/// [Registry] with a [StorageLayer].
+pub fn insert_fields<I, K, V>(span: &Span, fields: I)
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    span.with_subscriber(|(id, dispatch)| {
+        let Some(span) = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(id))
+        else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(storage) = extensions.get_mut::<Storage<'static>>() {
+            storage.values.extend(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (Cow::from(k.into()), Cow::from(v.into()))),
+            );
+        }
+    });
+}
+
+/// Value of the field `key` in the [Storage] of `span`, e.g. a `request_id`
+/// inserted by middleware.
+///
+/// Returns `None` if `span` is disabled or the subscriber isn't built on a
+/// [Registry] with a [StorageLayer].
+pub fn field(span: &Span, key: &str) -> Option<String> {
+    span.with_subscriber(|(id, dispatch)| {
+        let span = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(id))?;
+        let extensions = span.extensions();
+        extensions
+            .get::<Storage<'static>>()
+            .and_then(|storage| storage.values.get(key))
+            .map(|value| value.to_string())
+    })
+    .flatten()
+}
+
 impl Visit for Storage<'_> {
     /// Visit a signed 64-bit integer value.
     fn record_i64(&mut self, field: &Field, value: i64) {
         self.values
-            .insert(field.name(), Cow::from(value.to_string()));
+            .insert(Cow::from(field.name()), Cow::from(value.to_string()));
     }
 
     /// Visit an unsigned 64-bit integer value.
     fn record_u64(&mut self, field: &Field, value: u64) {
         self.values
-            .insert(field.name(), Cow::from(value.to_string()));
+            .insert(Cow::from(field.name()), Cow::from(value.to_string()));
     }
 
     /// Visit a boolean value.
     fn record_bool(&mut self, field: &Field, value: bool) {
         self.values
-            .insert(field.name(), Cow::from(value.to_string()));
+            .insert(Cow::from(field.name()), Cow::from(value.to_string()));
     }
 
     /// Visit a string value.
     fn record_str(&mut self, field: &Field, value: &str) {
         self.values
-            .insert(field.name(), Cow::from(value.to_string()));
+            .insert(Cow::from(field.name()), Cow::from(value.to_string()));
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
@@ -65,7 +212,8 @@ impl Visit for Storage<'_> {
             name if name.starts_with("log.") => (),
             _ => {
                 let debug_formatted = format!("{value:?}");
-                self.values.insert(field.name(), Cow::from(debug_formatted));
+                self.values
+                    .insert(Cow::from(field.name()), Cow::from(debug_formatted));
             }
         }
     }
@@ -76,16 +224,70 @@ impl Visit for Storage<'_> {
             _ => {
                 let display_formatted = format!("{value}");
                 self.values
-                    .insert(field.name(), Cow::from(display_formatted));
+                    .insert(Cow::from(field.name()), Cow::from(display_formatted));
             }
         }
     }
 }
 
+/// Visitor recording only the kept `fields` of an event into a [Storage].
+struct KeptFields<'s, 'a> {
+    storage: &'s mut Storage<'a>,
+    fields: &'s [String],
+}
+
+impl KeptFields<'_, '_> {
+    fn kept(&self, field: &Field) -> bool {
+        field.name() != MESSAGE_FIELD && self.fields.iter().any(|kept| kept == field.name())
+    }
+}
+
+impl Visit for KeptFields<'_, '_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if self.kept(field) {
+            self.storage.record_i64(field, value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if self.kept(field) {
+            self.storage.record_u64(field, value);
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if self.kept(field) {
+            self.storage.record_bool(field, value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.kept(field) {
+            self.storage.record_str(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if self.kept(field) {
+            self.storage.record_debug(field, value);
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if self.kept(field) {
+            self.storage.record_error(field, value);
+        }
+    }
+}
+
 impl<S> Layer<S> for StorageLayer
 where
     S: Subscriber + for<'span> LookupSpan<'span>,
 {
+    fn on_register_dispatch(&self, dispatch: &Dispatch) {
+        *self.dispatch.write() = Some(dispatch.downgrade());
+    }
+
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found");
 
@@ -99,7 +301,7 @@ where
                 .unwrap_or_default();
 
             inner.values.insert(
-                PARENT_SPAN,
+                Cow::from(PARENT_SPAN),
                 Cow::from(parent_span.id().into_u64().to_string()),
             );
             inner
@@ -136,9 +338,10 @@ where
             .zip(follows_extensions.get::<Storage<'_>>())
         {
             // insert "follows_from" span name
-            visitor
-                .values
-                .insert(FOLLOWS_FROM_FIELD, Cow::from(follows_span.name()));
+            visitor.values.insert(
+                Cow::from(FOLLOWS_FROM_FIELD),
+                Cow::from(follows_span.name()),
+            );
 
             // insert "follows_from" trace_id
             let follows_trace = follows_visitor
@@ -148,7 +351,7 @@ where
                 .to_string();
             visitor
                 .values
-                .insert(FOLLOWS_FROM_TRACE_ID, Cow::from(follows_trace));
+                .insert(Cow::from(FOLLOWS_FROM_TRACE_ID), Cow::from(follows_trace));
         };
     }
 
@@ -158,9 +361,12 @@ where
             extensions.get_mut::<Storage<'_>>().map(|visitor| {
                 if event
                     .fields()
-                    .any(|f| ON_EVENT_KEEP_FIELDS.contains(&f.name()))
+                    .any(|f| self.event_fields.iter().any(|k| k == f.name()))
                 {
-                    event.record(visitor);
+                    event.record(&mut KeptFields {
+                        storage: visitor,
+                        fields: &self.event_fields,
+                    });
                 }
             })
         });
@@ -188,8 +394,9 @@ where
             .get_mut::<Storage<'_>>()
             .expect("Visitor not found on 'record'");
 
-        visitor
-            .values
-            .insert(LATENCY_FIELD, Cow::from(format!("{elapsed_milliseconds}")));
+        visitor.values.insert(
+            Cow::from(LATENCY_FIELD),
+            Cow::from(format!("{elapsed_milliseconds}")),
+        );
     }
 }
@@ -0,0 +1,36 @@
+//! Build script exposing the git commit and compiler version to the admin
+//! server's build info.
+
+use std::{env, path::Path, process::Command};
+
+fn main() {
+    // Prefer an explicit `GIT_SHA`, e.g. passed as a Docker build argument
+    // where `.git` isn't available.
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=RUSTC_VERSION={rustc_version}");
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    // Only watch `.git` if present, as missing paths always rerun the script.
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|output| !output.is_empty())
+}
@@ -0,0 +1,191 @@
+#![warn(missing_debug_implementations, missing_docs, rust_2018_idioms)]
+
+//! Procedural macros for {{project-name}}.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Expr, FnArg, Ident, ItemFn, LitStr, Pat, ReturnType, Token, Type,
+};
+
+/// Prefix of span names picked up by the metrics layer, matching
+/// `tracing_layers::metrics_layer::METRIC_META_PREFIX`.
+const METRIC_META_PREFIX: &str = "record.";
+
+/// Instrument a function for the `record.*` metrics convention, deriving a
+/// `<name>_total` counter and a `<name>_duration_seconds` histogram via the
+/// metrics layer.
+///
+/// Expands to [`tracing::instrument`] with:
+///
+/// - a span named `record.<fn name>`;
+/// - a `metric_name` field, if `name` is given (otherwise metrics are named
+///   after the function);
+/// - a `metric_label_<label>` field per label, either a function argument
+///   (recorded with its `Display` implementation), or `label = <expr>`;
+/// - `err(Display)` for functions returning a `Result`, recording an `error`
+///   on `Err` returns, which sets the `result` label to `error`.
+///
+/// ```ignore
+/// #[instrument_metric(name = "db_event", labels(event_type, source = "api"))]
+/// async fn save_event(event_type: &str, payload: Payload) -> Result<(), AppError> {
+///     ...
+/// }
+/// ```
+///
+/// The span `level` defaults to `"info"`.
+///
+/// [`tracing::instrument`]: https://docs.rs/tracing/latest/tracing/attr.instrument.html
+#[proc_macro_attribute]
+pub fn instrument_metric(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let item = parse_macro_input!(item as ItemFn);
+
+    match expand(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: Args, item: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let span_name = format!("{METRIC_META_PREFIX}{}", item.sig.ident);
+    let level = args
+        .level
+        .unwrap_or_else(|| LitStr::new("info", Span::call_site()));
+
+    let arguments = item
+        .sig
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            FnArg::Typed(pat_type) => match &*pat_type.pat {
+                Pat::Ident(pat_ident) => Some(pat_ident.ident.clone()),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut fields = vec![];
+    if let Some(name) = args.name {
+        fields.push(quote!(metric_name = #name));
+    }
+    for label in args.labels {
+        let field = format_ident!("metric_label_{}", label.key);
+        match label.value {
+            Some(value) => fields.push(quote!(#field = %#value)),
+            None if arguments.contains(&label.key) => {
+                let argument = &label.key;
+                fields.push(quote!(#field = %#argument))
+            }
+            None => {
+                let message = format!(
+                    "`{key}` is not an argument of this function; use `{key} = <expr>` for other labels",
+                    key = label.key
+                );
+                return Err(syn::Error::new(label.key.span(), message));
+            }
+        }
+    }
+
+    let err = returns_result(&item.sig.output).then(|| quote!(, err(Display)));
+
+    Ok(quote! {
+        #[::tracing::instrument(
+            level = #level,
+            name = #span_name,
+            skip_all,
+            fields(#(#fields),*)
+            #err
+        )]
+        #item
+    })
+}
+
+/// Whether the function's return type is (syntactically) a `Result`,
+/// including aliases like `anyhow::Result`.
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident == "Result")
+                .unwrap_or(false),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
+
+/// Parsed `#[instrument_metric(...)]` arguments.
+#[derive(Default)]
+struct Args {
+    name: Option<LitStr>,
+    level: Option<LitStr>,
+    labels: Vec<Label>,
+}
+
+/// A label, either a function argument or `key = <expr>`.
+struct Label {
+    key: Ident,
+    value: Option<Expr>,
+}
+
+impl Parse for Label {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let key = input.parse()?;
+        let value = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        Ok(Self { key, value })
+    }
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let mut args = Args::default();
+
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            match key.to_string().as_str() {
+                "name" => {
+                    input.parse::<Token![=]>()?;
+                    args.name = Some(input.parse()?);
+                }
+                "level" => {
+                    input.parse::<Token![=]>()?;
+                    args.level = Some(input.parse()?);
+                }
+                "labels" => {
+                    let content;
+                    syn::parenthesized!(content in input);
+                    args.labels = Punctuated::<Label, Token![,]>::parse_terminated(&content)?
+                        .into_iter()
+                        .collect();
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        "expected `name`, `level` or `labels`",
+                    ))
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(args)
+    }
+}
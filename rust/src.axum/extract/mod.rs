@@ -1,3 +1,4 @@
 //! Custom [axum::extract] Extractors.
 
 pub mod json;
+pub mod request_id;
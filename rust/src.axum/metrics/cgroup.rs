@@ -0,0 +1,204 @@
+//! Container (cgroup) limits and usage, read from the cgroup filesystem, for
+//! alerting on container-level saturation.
+//!
+//! Supports both cgroup v2 (unified) and v1 hierarchies.
+
+use crate::metrics::registry::MetricDescription;
+use metrics::Unit;
+use std::{fs, path::Path};
+
+/// Root of the cgroup filesystem, as mounted in containers.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// cgroup v1 reports "no memory limit" as a huge page-aligned number.
+const V1_UNLIMITED_MEMORY: u64 = 1 << 62;
+
+/// Descriptions of cgroup metrics.
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::gauge(
+            "container_cpu_quota_cores",
+            "CPU quota of the container, in cores.",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::counter(
+            "container_cpu_throttled_periods_total",
+            "Total CPU scheduler periods in which the container was throttled.",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::counter(
+            "container_cpu_throttled_microseconds_total",
+            "Total time the container was throttled, in microseconds.",
+        )
+        .with_unit(Unit::Microseconds),
+        MetricDescription::gauge(
+            "container_memory_limit_bytes",
+            "Memory limit of the container in bytes.",
+        )
+        .with_unit(Unit::Bytes),
+        MetricDescription::gauge(
+            "container_memory_usage_bytes",
+            "Memory used by the container in bytes.",
+        )
+        .with_unit(Unit::Bytes),
+    ]
+}
+
+/// cgroup limits and usage. Unset values are either unlimited or not
+/// available.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CgroupStats {
+    cpu_quota_cores: Option<f64>,
+    cpu_throttled_periods: Option<u64>,
+    cpu_throttled_microseconds: Option<u64>,
+    memory_limit_bytes: Option<u64>,
+    memory_usage_bytes: Option<u64>,
+}
+
+/// Record cgroup metrics of the current container, if any.
+pub(crate) fn collect() {
+    let stats = read(Path::new(CGROUP_ROOT));
+
+    if let Some(cores) = stats.cpu_quota_cores {
+        metrics::gauge!("container_cpu_quota_cores").set(cores);
+    }
+    if let Some(periods) = stats.cpu_throttled_periods {
+        metrics::counter!("container_cpu_throttled_periods_total").absolute(periods);
+    }
+    if let Some(usec) = stats.cpu_throttled_microseconds {
+        metrics::counter!("container_cpu_throttled_microseconds_total").absolute(usec);
+    }
+    if let Some(limit) = stats.memory_limit_bytes {
+        metrics::gauge!("container_memory_limit_bytes").set(limit as f64);
+    }
+    if let Some(usage) = stats.memory_usage_bytes {
+        metrics::gauge!("container_memory_usage_bytes").set(usage as f64);
+    }
+}
+
+/// Read cgroup stats under `root`, from the v2 hierarchy if mounted, or v1
+/// otherwise.
+fn read(root: &Path) -> CgroupStats {
+    let read = |path: &str| fs::read_to_string(root.join(path)).ok();
+
+    if root.join("cgroup.controllers").exists() {
+        let cpu_stat = read("cpu.stat").unwrap_or_default();
+        CgroupStats {
+            cpu_quota_cores: read("cpu.max").and_then(|max| parse_cpu_max(&max)),
+            cpu_throttled_periods: stat(&cpu_stat, "nr_throttled"),
+            cpu_throttled_microseconds: stat(&cpu_stat, "throttled_usec"),
+            memory_limit_bytes: read("memory.max").and_then(|max| max.trim().parse().ok()),
+            memory_usage_bytes: read("memory.current")
+                .and_then(|current| current.trim().parse().ok()),
+        }
+    } else {
+        let cpu_stat = read("cpu/cpu.stat").unwrap_or_default();
+        let quota = read("cpu/cpu.cfs_quota_us").and_then(|quota| quota.trim().parse::<i64>().ok());
+        let period =
+            read("cpu/cpu.cfs_period_us").and_then(|period| period.trim().parse::<i64>().ok());
+        CgroupStats {
+            cpu_quota_cores: match (quota, period) {
+                (Some(quota), Some(period)) if quota > 0 && period > 0 => {
+                    Some(quota as f64 / period as f64)
+                }
+                _ => None,
+            },
+            cpu_throttled_periods: stat(&cpu_stat, "nr_throttled"),
+            cpu_throttled_microseconds: stat(&cpu_stat, "throttled_time").map(|nsec| nsec / 1_000),
+            memory_limit_bytes: read("memory/memory.limit_in_bytes")
+                .and_then(|limit| limit.trim().parse().ok())
+                .filter(|limit| *limit < V1_UNLIMITED_MEMORY),
+            memory_usage_bytes: read("memory/memory.usage_in_bytes")
+                .and_then(|usage| usage.trim().parse().ok()),
+        }
+    }
+}
+
+/// CPU quota in cores from a v2 `cpu.max` (`<quota|max> <period>`).
+fn parse_cpu_max(max: &str) -> Option<f64> {
+    let mut parts = max.split_whitespace();
+    let quota = parts.next()?.parse::<f64>().ok()?;
+    let period = parts.next()?.parse::<f64>().ok()?;
+    (period > 0.0).then(|| quota / period)
+}
+
+/// Value of `key` in a flat-keyed stat file, e.g. `cpu.stat`.
+fn stat(stats: &str, key: &str) -> Option<u64> {
+    stats.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        (name == key).then(|| value.trim().parse().ok())?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cgroup_dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("cgroup-{name}-{}", std::process::id()));
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn reads_cgroup_v2() {
+        let root = cgroup_dir(
+            "v2",
+            &[
+                ("cgroup.controllers", "cpu memory"),
+                ("cpu.max", "150000 100000\n"),
+                (
+                    "cpu.stat",
+                    "usage_usec 100\nnr_throttled 7\nthrottled_usec 2500\n",
+                ),
+                ("memory.max", "max\n"),
+                ("memory.current", "1048576\n"),
+            ],
+        );
+
+        assert_eq!(
+            read(&root),
+            CgroupStats {
+                cpu_quota_cores: Some(1.5),
+                cpu_throttled_periods: Some(7),
+                cpu_throttled_microseconds: Some(2500),
+                memory_limit_bytes: None,
+                memory_usage_bytes: Some(1_048_576),
+            }
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reads_cgroup_v1() {
+        let root = cgroup_dir(
+            "v1",
+            &[
+                ("cpu/cpu.cfs_quota_us", "-1\n"),
+                ("cpu/cpu.cfs_period_us", "100000\n"),
+                (
+                    "cpu/cpu.stat",
+                    "nr_periods 10\nnr_throttled 1\nthrottled_time 1500000\n",
+                ),
+                ("memory/memory.limit_in_bytes", "536870912\n"),
+                ("memory/memory.usage_in_bytes", "1024\n"),
+            ],
+        );
+
+        assert_eq!(
+            read(&root),
+            CgroupStats {
+                cpu_quota_cores: None,
+                cpu_throttled_periods: Some(1),
+                cpu_throttled_microseconds: Some(1500),
+                memory_limit_bytes: Some(536_870_912),
+                memory_usage_bytes: Some(1024),
+            }
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,4 +1,12 @@
 //! Metrics capture and Prometheus recorder.
 
+#[cfg(feature = "alloc-metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc-metrics")))]
+pub mod allocator;
+pub mod cardinality;
+pub(crate) mod cgroup;
+pub mod otlp;
 pub mod process;
 pub mod prom;
+pub mod registry;
+pub(crate) mod runtime;
@@ -0,0 +1,447 @@
+//! Metrics OTLP push exporter.
+//!
+//! Bridges the [metrics] facade to Opentelemetry instruments, which are
+//! exported over the [OTLP protocol] on an interval, alongside the Prometheus
+//! recorder.
+//!
+//! [OTLP protocol]: <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/otlp.md>
+
+use crate::{
+    metrics::{prom::EXPONENTIAL_SECONDS, registry},
+    settings::{AppEnvironment, Otel},
+    tracer::{exporter, resource, METRICS_PATH},
+};
+use anyhow::{anyhow, Result};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use metrics_util::registry::{Registry, Storage};
+use opentelemetry::{
+    metrics::{self as otel_metrics, Meter, MeterProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::MetricsExporterBuilder;
+use opentelemetry_sdk::{
+    metrics::{
+        new_view,
+        reader::{AggregationSelector, DefaultAggregationSelector, DefaultTemporalitySelector},
+        Aggregation, Instrument, InstrumentKind, PeriodicReader, SdkMeterProvider, Stream,
+    },
+    runtime,
+};
+use parking_lot::RwLock;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Initialize an Opentelemetry [SdkMeterProvider] pushing metrics via the
+/// [OTLP protocol] on the interval set in [Otel] settings, to the same
+/// endpoint and with the same resource attributes as traces.
+///
+/// The provider is also installed as the global meter provider.
+///
+/// [OTLP protocol]: <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/otlp.md>
+pub fn init_meter_provider(
+    settings: &Otel,
+    environment: AppEnvironment,
+) -> Result<SdkMeterProvider> {
+    let exporter = MetricsExporterBuilder::from(exporter(settings, METRICS_PATH)?)
+        .build_metrics_exporter(
+            Box::new(DefaultTemporalitySelector::new()),
+            Box::new(SecondsBucketsSelector),
+        )
+        .map_err(|e| anyhow!("failed to intialize meter provider: {:#?}", e))?;
+    let reader = PeriodicReader::builder(exporter, runtime::Tokio)
+        .with_interval(settings.metrics_export_interval())
+        .build();
+
+    let mut builder = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource(settings, environment));
+
+    // Histograms registered with custom buckets.
+    for (name, buckets) in registry::histogram_buckets() {
+        let view = new_view(
+            Instrument::new().name(name),
+            Stream::new().aggregation(Aggregation::ExplicitBucketHistogram {
+                boundaries: buckets,
+                record_min_max: true,
+            }),
+        )
+        .map_err(|e| anyhow!("failed to create histogram view: {:#?}", e))?;
+        builder = builder.with_view(view);
+    }
+
+    let provider = builder.build();
+    opentelemetry::global::set_meter_provider(provider.clone());
+
+    Ok(provider)
+}
+
+/// Aggregates histograms into the same buckets used by the Prometheus
+/// recorder for `_duration_seconds` metrics.
+#[derive(Debug)]
+struct SecondsBucketsSelector;
+
+impl AggregationSelector for SecondsBucketsSelector {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        match kind {
+            InstrumentKind::Histogram => Aggregation::ExplicitBucketHistogram {
+                boundaries: EXPONENTIAL_SECONDS.to_vec(),
+                record_min_max: true,
+            },
+            _ => DefaultAggregationSelector::new().aggregation(kind),
+        }
+    }
+}
+
+/// [Recorder] recording [metrics] counters, gauges and histograms as
+/// Opentelemetry instruments.
+pub struct OtlpRecorder {
+    registry: Registry<Key, OtlpStorage>,
+    descriptions: Arc<RwLock<HashMap<String, Description>>>,
+}
+
+impl OtlpRecorder {
+    /// Create a new recorder, with instruments created from a [Meter] of the
+    /// given `provider`.
+    pub fn new(provider: &SdkMeterProvider) -> Self {
+        let descriptions = Arc::new(RwLock::new(HashMap::new()));
+        let storage = OtlpStorage {
+            meter: provider.meter(env!("CARGO_PKG_NAME")),
+            descriptions: descriptions.clone(),
+        };
+
+        Self {
+            registry: Registry::new(storage),
+            descriptions,
+        }
+    }
+
+    fn describe(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.descriptions
+            .write()
+            .insert(key.as_str().to_string(), Description { unit, description });
+    }
+}
+
+impl std::fmt::Debug for OtlpRecorder {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("OtlpRecorder")
+            .field("descriptions", &self.descriptions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recorder for OtlpRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        self.registry
+            .get_or_create_counter(key, |c| Counter::from_arc(Arc::new(c.clone())))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        self.registry
+            .get_or_create_gauge(key, |g| Gauge::from_arc(Arc::new(g.clone())))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        self.registry
+            .get_or_create_histogram(key, |h| Histogram::from_arc(Arc::new(h.clone())))
+    }
+}
+
+#[derive(Debug)]
+struct Description {
+    unit: Option<Unit>,
+    description: SharedString,
+}
+
+/// Registry storage creating an instrument per metric key, described with
+/// any description recorded before the key was first registered.
+#[derive(Debug)]
+struct OtlpStorage {
+    meter: Meter,
+    descriptions: Arc<RwLock<HashMap<String, Description>>>,
+}
+
+macro_rules! instrument {
+    ($builder:expr, $descriptions:expr, $key:expr) => {{
+        let mut builder = $builder;
+        if let Some(Description { unit, description }) = $descriptions.get($key.name()) {
+            builder = builder.with_description(description.to_string());
+            if let Some(unit) = unit {
+                builder = builder.with_unit(otel_metrics::Unit::new(unit.as_canonical_label()));
+            }
+        }
+        builder.init()
+    }};
+}
+
+impl Storage<Key> for OtlpStorage {
+    type Counter = OtlpCounter;
+    type Gauge = OtlpGauge;
+    type Histogram = OtlpHistogram;
+
+    fn counter(&self, key: &Key) -> Self::Counter {
+        let descriptions = self.descriptions.read();
+        OtlpCounter {
+            value: Arc::new(AtomicU64::new(0)),
+            counter: instrument!(
+                self.meter.u64_counter(key.name().to_string()),
+                descriptions,
+                key
+            ),
+            attributes: attributes(key),
+        }
+    }
+
+    fn gauge(&self, key: &Key) -> Self::Gauge {
+        let descriptions = self.descriptions.read();
+        OtlpGauge {
+            value: Arc::new(AtomicU64::new(0f64.to_bits())),
+            gauge: instrument!(
+                self.meter.f64_gauge(key.name().to_string()),
+                descriptions,
+                key
+            ),
+            attributes: attributes(key),
+        }
+    }
+
+    fn histogram(&self, key: &Key) -> Self::Histogram {
+        let descriptions = self.descriptions.read();
+        OtlpHistogram {
+            histogram: instrument!(
+                self.meter.f64_histogram(key.name().to_string()),
+                descriptions,
+                key
+            ),
+            attributes: attributes(key),
+        }
+    }
+}
+
+fn attributes(key: &Key) -> Arc<[KeyValue]> {
+    key.labels()
+        .map(|label| KeyValue::new(label.key().to_string(), label.value().to_string()))
+        .collect()
+}
+
+/// Counter handle, keeping the running total to turn absolute values into
+/// increments.
+#[derive(Clone, Debug)]
+struct OtlpCounter {
+    value: Arc<AtomicU64>,
+    counter: otel_metrics::Counter<u64>,
+    attributes: Arc<[KeyValue]>,
+}
+
+impl CounterFn for OtlpCounter {
+    fn increment(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+        self.counter.add(value, &self.attributes);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.value.fetch_max(value, Ordering::Relaxed);
+        if value > previous {
+            self.counter.add(value - previous, &self.attributes);
+        }
+    }
+}
+
+/// Gauge handle, keeping the current value to support increments and
+/// decrements.
+#[derive(Clone, Debug)]
+struct OtlpGauge {
+    value: Arc<AtomicU64>,
+    gauge: otel_metrics::Gauge<f64>,
+    attributes: Arc<[KeyValue]>,
+}
+
+impl OtlpGauge {
+    fn update(&self, f: impl Fn(f64) -> f64) {
+        let previous = self
+            .value
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |bits| {
+                Some(f(f64::from_bits(bits)).to_bits())
+            })
+            .unwrap_or_else(|bits| bits);
+        self.gauge
+            .record(f(f64::from_bits(previous)), &self.attributes);
+    }
+}
+
+impl GaugeFn for OtlpGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value)
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value)
+    }
+
+    fn set(&self, value: f64) {
+        self.value.store(value.to_bits(), Ordering::Release);
+        self.gauge.record(value, &self.attributes);
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OtlpHistogram {
+    histogram: otel_metrics::Histogram<f64>,
+    attributes: Arc<[KeyValue]>,
+}
+
+impl HistogramFn for OtlpHistogram {
+    fn record(&self, value: f64) {
+        self.histogram.record(value, &self.attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metrics::registry::MetricDescription,
+        settings::{ExporterMode, LoggedBaggage, OtlpProtocol, Sampling},
+    };
+    use opentelemetry_proto::tonic::{
+        collector::metrics::v1::{
+            metrics_service_server::{MetricsService, MetricsServiceServer},
+            ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+        },
+        metrics::v1::{metric::Data, number_data_point::Value, Metric},
+    };
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Server, Request, Response, Status};
+
+    /// Fake OTLP collector keeping received metrics export requests.
+    #[derive(Clone, Default)]
+    struct FakeCollector {
+        requests: Arc<Mutex<Vec<ExportMetricsServiceRequest>>>,
+    }
+
+    #[tonic::async_trait]
+    impl MetricsService for FakeCollector {
+        async fn export(
+            &self,
+            request: Request<ExportMetricsServiceRequest>,
+        ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+            self.requests.lock().unwrap().push(request.into_inner());
+            Ok(Response::new(ExportMetricsServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    impl FakeCollector {
+        fn metric(&self, name: &str) -> Option<Metric> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|r| &r.resource_metrics)
+                .flat_map(|r| &r.scope_metrics)
+                .flat_map(|s| &s.metrics)
+                .find(|m| m.name == name)
+                .cloned()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pushes_metrics_facade_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = FakeCollector::default();
+        tokio::spawn(
+            Server::builder()
+                .add_service(MetricsServiceServer::new(collector.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let settings = Otel {
+            exporter: ExporterMode::Otlp,
+            exporter_otlp_endpoint: format!("http://{addr}").parse().unwrap(),
+            protocol: OtlpProtocol::Grpc,
+            headers: HashMap::new(),
+            timeout_ms: None,
+            compression: None,
+            resource_attributes: HashMap::new(),
+            export_logs: false,
+            export_metrics: true,
+            metrics_export_interval_ms: None,
+            sampling: Sampling::default(),
+            propagators: vec![],
+            logged_baggage: LoggedBaggage::default(),
+        };
+        registry::register(
+            MetricDescription::histogram("test_custom_seconds", "Custom buckets.")
+                .with_buckets(&[0.5, 1.0]),
+        );
+        let provider = init_meter_provider(&settings, AppEnvironment::Local).unwrap();
+        let recorder = OtlpRecorder::new(&provider);
+
+        metrics::with_local_recorder(&recorder, || {
+            metrics::describe_counter!("test_requests_total", "Requests.");
+            metrics::counter!("test_requests_total", "route" => "/ping").increment(2);
+            metrics::counter!("test_requests_total", "route" => "/ping").increment(1);
+            metrics::gauge!("test_connections").set(5.0);
+            metrics::gauge!("test_connections").decrement(2.0);
+            metrics::histogram!("test_duration_seconds").record(0.2);
+            metrics::histogram!("test_custom_seconds").record(0.7);
+        });
+
+        provider.force_flush().unwrap();
+
+        let counter = collector.metric("test_requests_total").unwrap();
+        assert_eq!(counter.description, "Requests.");
+        let Some(Data::Sum(sum)) = counter.data else {
+            panic!("expected a sum");
+        };
+        assert_eq!(sum.data_points[0].value, Some(Value::AsInt(3)));
+        assert_eq!(sum.data_points[0].attributes[0].key, "route");
+
+        let Some(Data::Gauge(gauge)) = collector.metric("test_connections").unwrap().data else {
+            panic!("expected a gauge");
+        };
+        assert_eq!(gauge.data_points[0].value, Some(Value::AsDouble(3.0)));
+
+        let Some(Data::Histogram(histogram)) =
+            collector.metric("test_duration_seconds").unwrap().data
+        else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(histogram.data_points[0].count, 1);
+        assert_eq!(
+            histogram.data_points[0].explicit_bounds,
+            EXPONENTIAL_SECONDS.to_vec()
+        );
+
+        let Some(Data::Histogram(histogram)) =
+            collector.metric("test_custom_seconds").unwrap().data
+        else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(histogram.data_points[0].explicit_bounds, vec![0.5, 1.0]);
+    }
+}
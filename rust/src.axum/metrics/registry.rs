@@ -0,0 +1,256 @@
+//! Registry of metric descriptions, units and histogram buckets.
+//!
+//! Built-in metrics (http server/client and process metrics) are registered
+//! up front. Metrics derived by the
+//! [MetricsLayer](crate::tracing_layers::metrics_layer::MetricsLayer) from
+//! `record.*` spans can be described, and given custom buckets, via
+//! [register_span_metric], before the recorder is set up with
+//! [setup_metrics_recorder](crate::metrics::prom::setup_metrics_recorder).
+//!
+//! All registered metrics are listed by the metrics catalog endpoint.
+
+use crate::{
+    metrics::{cardinality, cgroup, process, runtime},
+    middleware::{client, metrics as http_metrics, reqwest_retry},
+};
+use metrics::Unit;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::warn;
+
+static REGISTRY: Lazy<RwLock<BTreeMap<String, MetricDescription>>> = Lazy::new(|| {
+    let builtin = http_metrics::descriptions()
+        .into_iter()
+        .chain(client::metrics::descriptions())
+        .chain(reqwest_retry::descriptions())
+        .chain(process::descriptions())
+        .chain(process::host_descriptions())
+        .chain(cgroup::descriptions())
+        .chain(runtime::descriptions())
+        .chain(cardinality::descriptions());
+
+    #[cfg(feature = "alloc-metrics")]
+    let builtin = builtin.chain(crate::metrics::allocator::descriptions());
+
+    let builtin = builtin
+        .map(|description| (description.name.clone(), description))
+        .collect();
+
+    RwLock::new(builtin)
+});
+
+/// Set once histogram buckets have been handed to the recorder(s), after
+/// which newly registered buckets have no effect.
+static BUCKETS_APPLIED: AtomicBool = AtomicBool::new(false);
+
+/// Kind of a registered metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// Monotonically increasing counter.
+    Counter,
+    /// Gauge, which can go up and down.
+    Gauge,
+    /// Histogram of recorded values.
+    Histogram,
+}
+
+/// Description of a metric, as rendered in Prometheus `HELP`/`TYPE` lines
+/// and the metrics catalog.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MetricDescription {
+    /// Metric name.
+    pub name: String,
+    /// Metric kind.
+    pub kind: MetricKind,
+    /// Metric unit, if any.
+    #[serde(
+        serialize_with = "serialize_unit",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub unit: Option<Unit>,
+    /// Human-readable description.
+    pub description: String,
+    /// Histogram bucket boundaries, if not the defaults.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<f64>>,
+}
+
+impl MetricDescription {
+    /// Describe a counter.
+    pub fn counter(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self::new(name, MetricKind::Counter, description)
+    }
+
+    /// Describe a gauge.
+    pub fn gauge(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self::new(name, MetricKind::Gauge, description)
+    }
+
+    /// Describe a histogram.
+    pub fn histogram(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self::new(name, MetricKind::Histogram, description)
+    }
+
+    /// Set the metric's [Unit].
+    pub fn with_unit(mut self, unit: Unit) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    /// Set custom bucket boundaries for a histogram.
+    pub fn with_buckets(mut self, buckets: &[f64]) -> Self {
+        self.buckets = Some(buckets.to_vec());
+        self
+    }
+
+    fn new(name: impl Into<String>, kind: MetricKind, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            unit: None,
+            description: description.into(),
+            buckets: None,
+        }
+    }
+
+    /// Describe the metric to the installed recorder.
+    fn describe(&self) {
+        let name = self.name.clone();
+        let description = self.description.clone();
+        match (self.kind, self.unit) {
+            (MetricKind::Counter, Some(unit)) => {
+                metrics::describe_counter!(name, unit, description)
+            }
+            (MetricKind::Counter, None) => metrics::describe_counter!(name, description),
+            (MetricKind::Gauge, Some(unit)) => metrics::describe_gauge!(name, unit, description),
+            (MetricKind::Gauge, None) => metrics::describe_gauge!(name, description),
+            (MetricKind::Histogram, Some(unit)) => {
+                metrics::describe_histogram!(name, unit, description)
+            }
+            (MetricKind::Histogram, None) => metrics::describe_histogram!(name, description),
+        }
+    }
+}
+
+/// Register (or replace) a metric description.
+///
+/// The description is passed on to the installed recorder right away, and
+/// again when the recorder is set up. Custom histogram buckets only apply if
+/// registered before the recorder is set up.
+pub fn register(description: MetricDescription) {
+    if description.buckets.is_some() && BUCKETS_APPLIED.load(Ordering::Relaxed) {
+        warn!(
+            subject = "metrics.registry",
+            category = "metrics",
+            metric = description.name,
+            "histogram buckets registered after recorder setup are ignored"
+        );
+    }
+
+    description.describe();
+    REGISTRY
+        .write()
+        .insert(description.name.clone(), description);
+}
+
+/// Register descriptions for the `<name>_total` counter and
+/// `<name>_duration_seconds` histogram derived by the
+/// [MetricsLayer](crate::tracing_layers::metrics_layer::MetricsLayer) from
+/// `record.*` spans, with optional custom histogram buckets (in seconds).
+///
+/// ```ignore
+/// registry::register_span_metric("db_event", "Database events.", Some(&[0.001, 0.01, 0.1]));
+/// ```
+pub fn register_span_metric(name: &str, description: &str, buckets: Option<&[f64]>) {
+    register(
+        MetricDescription::counter(format!("{name}_total"), format!("{description} Count."))
+            .with_unit(Unit::Count),
+    );
+
+    let histogram = MetricDescription::histogram(
+        format!("{name}_duration_seconds"),
+        format!("{description} Duration in seconds."),
+    )
+    .with_unit(Unit::Seconds);
+
+    register(match buckets {
+        Some(buckets) => histogram.with_buckets(buckets),
+        None => histogram,
+    });
+}
+
+/// All registered metric descriptions, sorted by name.
+pub fn catalog() -> Vec<MetricDescription> {
+    REGISTRY.read().values().cloned().collect()
+}
+
+/// Describe all registered metrics to the installed recorder.
+pub(crate) fn describe_all() {
+    for description in REGISTRY.read().values() {
+        description.describe();
+    }
+}
+
+/// Custom histogram buckets by metric name, marking them as applied.
+pub(crate) fn histogram_buckets() -> Vec<(String, Vec<f64>)> {
+    BUCKETS_APPLIED.store(true, Ordering::Relaxed);
+
+    REGISTRY
+        .read()
+        .values()
+        .filter_map(|description| {
+            description
+                .buckets
+                .clone()
+                .map(|buckets| (description.name.clone(), buckets))
+        })
+        .collect()
+}
+
+fn serialize_unit<S: Serializer>(unit: &Option<Unit>, serializer: S) -> Result<S::Ok, S::Error> {
+    match unit {
+        Some(unit) => serializer.serialize_str(unit.as_str()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_builtin_and_span_metrics() {
+        register_span_metric("registry_test_event", "Test events.", Some(&[0.1, 1.0]));
+
+        let catalog = catalog();
+        let find = |name: &str| catalog.iter().find(|description| description.name == name);
+
+        let requests = find("http_requests_total").unwrap();
+        assert_eq!(requests.kind, MetricKind::Counter);
+        assert!(find("client_http_request_duration_seconds").is_some());
+        assert!(find("process_memory_bytes").is_some());
+
+        let histogram = find("registry_test_event_duration_seconds").unwrap();
+        assert_eq!(histogram.kind, MetricKind::Histogram);
+        assert_eq!(histogram.buckets, Some(vec![0.1, 1.0]));
+        assert_eq!(
+            serde_json::to_value(histogram).unwrap(),
+            serde_json::json!({
+                "name": "registry_test_event_duration_seconds",
+                "kind": "histogram",
+                "unit": "seconds",
+                "description": "Test events. Duration in seconds.",
+                "buckets": [0.1, 1.0],
+            })
+        );
+        assert!(histogram_buckets()
+            .iter()
+            .any(|(name, _)| name == "registry_test_event_duration_seconds"));
+    }
+}
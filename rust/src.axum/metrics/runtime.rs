@@ -0,0 +1,133 @@
+//! Tokio runtime metrics: workers, queue depth, busy ratio and task counts.
+
+use crate::metrics::registry::MetricDescription;
+use metrics::Unit;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Descriptions of tokio runtime metrics.
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    let descriptions = vec![
+        MetricDescription::gauge("tokio_workers", "Number of runtime worker threads.")
+            .with_unit(Unit::Count),
+        MetricDescription::gauge(
+            "tokio_alive_tasks",
+            "Number of tasks alive (spawned and not yet completed) in the runtime.",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::gauge(
+            "tokio_global_queue_depth",
+            "Number of tasks scheduled in the runtime's global queue.",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::gauge(
+            "tokio_busy_ratio",
+            "Ratio (0 to 1) of time worker threads were busy since the last collection.",
+        ),
+        MetricDescription::counter(
+            "tokio_worker_park_total",
+            "Total times worker threads parked, waiting for work.",
+        )
+        .with_unit(Unit::Count),
+    ];
+
+    #[cfg(tokio_unstable)]
+    let descriptions = {
+        let mut descriptions = descriptions;
+        descriptions.push(
+            MetricDescription::gauge(
+                "tokio_blocking_threads",
+                "Number of threads used by the runtime's blocking pool.",
+            )
+            .with_unit(Unit::Count),
+        );
+        descriptions.push(
+            MetricDescription::counter(
+                "tokio_spawned_tasks_total",
+                "Total tasks spawned in the runtime.",
+            )
+            .with_unit(Unit::Count),
+        );
+        descriptions
+    };
+
+    descriptions
+}
+
+/// Collects metrics of a tokio runtime, keeping track of worker busy time
+/// between collections to report a busy ratio.
+#[derive(Debug)]
+pub(crate) struct RuntimeCollector {
+    handle: Handle,
+    busy: Duration,
+    collected_at: Instant,
+}
+
+impl RuntimeCollector {
+    /// Create a [RuntimeCollector] for the runtime of the given [Handle].
+    pub(crate) fn new(handle: Handle) -> Self {
+        let busy = total_busy_duration(&handle);
+        Self {
+            handle,
+            busy,
+            collected_at: Instant::now(),
+        }
+    }
+
+    /// Record runtime metrics.
+    pub(crate) fn collect(&mut self) {
+        let runtime = self.handle.metrics();
+        let workers = runtime.num_workers();
+
+        let busy = total_busy_duration(&self.handle);
+        let elapsed = self.collected_at.elapsed().as_secs_f64() * workers as f64;
+        if elapsed > 0.0 {
+            let busy_since = busy.saturating_sub(self.busy).as_secs_f64();
+            metrics::gauge!("tokio_busy_ratio").set((busy_since / elapsed).min(1.0));
+        }
+        self.busy = busy;
+        self.collected_at = Instant::now();
+
+        let parks = (0..workers)
+            .map(|worker| runtime.worker_park_count(worker))
+            .sum::<u64>();
+
+        metrics::gauge!("tokio_workers").set(workers as f64);
+        metrics::gauge!("tokio_alive_tasks").set(runtime.num_alive_tasks() as f64);
+        metrics::gauge!("tokio_global_queue_depth").set(runtime.global_queue_depth() as f64);
+        metrics::counter!("tokio_worker_park_total").absolute(parks);
+
+        #[cfg(tokio_unstable)]
+        {
+            metrics::gauge!("tokio_blocking_threads").set(runtime.num_blocking_threads() as f64);
+            metrics::counter!("tokio_spawned_tasks_total").absolute(runtime.spawned_tasks_count());
+        }
+    }
+}
+
+fn total_busy_duration(handle: &Handle) -> Duration {
+    let runtime = handle.metrics();
+    (0..runtime.num_workers())
+        .map(|worker| runtime.worker_total_busy_duration(worker))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_gauge, test_utils::LocalRecorder};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn collects_runtime_metrics() {
+        let recorder = LocalRecorder::install();
+        let mut collector = RuntimeCollector::new(Handle::current());
+
+        tokio::spawn(async {}).await.unwrap();
+        collector.collect();
+
+        assert_gauge!("tokio_workers", &[], 2.0);
+        let ratio = recorder.gauge("tokio_busy_ratio", &[]).unwrap();
+        assert!((0.0..=1.0).contains(&ratio));
+        assert!(recorder.counter("tokio_worker_park_total", &[]).is_some());
+    }
+}
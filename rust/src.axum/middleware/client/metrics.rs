@@ -1,6 +1,8 @@
 //! Middleware for tracking metrics on each client [reqwest::Request].
 
+use crate::metrics::{cardinality, registry::MetricDescription};
 use http::Extensions;
+use metrics::Unit;
 use reqwest_middleware::Middleware as ReqwestMiddleware;
 use std::time::Instant;
 
@@ -11,6 +13,22 @@ const NONE: &str = "none";
 const RESULT: &str = "result";
 const STATUS: &str = "status";
 
+/// Descriptions of metrics recorded by the [Metrics] middleware.
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![
+        MetricDescription::counter(
+            "client_http_requests_total",
+            "Outbound HTTP client requests.",
+        )
+        .with_unit(Unit::Count),
+        MetricDescription::histogram(
+            "client_http_request_duration_seconds",
+            "Outbound HTTP client request duration in seconds.",
+        )
+        .with_unit(Unit::Seconds),
+    ]
+}
+
 /// Metrics struct for use as part of middleware.
 #[derive(Debug)]
 pub struct Metrics {
@@ -29,7 +47,11 @@ impl ReqwestMiddleware for Metrics {
         let now = Instant::now();
 
         let url = request.url().clone();
-        let request_path = url.path().to_string();
+        let request_path = cardinality::guard().label(
+            "client_http_requests_total",
+            "request_path",
+            url.path().to_string(),
+        );
         let method = request.method().clone();
 
         let result = next.run(request, extensions).await;
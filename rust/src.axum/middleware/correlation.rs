@@ -0,0 +1,199 @@
+//! Middleware correlating every log line of a request with its request id
+//! and [OpenTelemetry] trace.
+//!
+//! The request's [RequestId] (as set by [MakeRequestUlid]) and the trace
+//! and span ids of the root request span are inserted into the span's
+//! [Storage](crate::tracing_layers::storage_layer::Storage), so that they're
+//! logged with every event within the request, and inherited by its spans.
+//! The reqwest [Logger](crate::middleware::logging::Logger) forwards the
+//! request id to outbound calls.
+//!
+//! Log layers only see the request span, and so its stored ids, if their
+//! filter enables it: give them a
+//! [StorageReader](crate::tracing_layers::storage_layer::StorageReader) of
+//! [FIELDS] to log the ids whatever their log filter directive.
+//!
+//! [OpenTelemetry]: <https://opentelemetry.io/>
+//! [MakeRequestUlid]: crate::middleware::request_ulid::MakeRequestUlid
+
+use crate::tracing_layers::storage_layer;
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::HeaderName;
+use opentelemetry::trace::TraceContextExt;
+use tower_http::request_id::RequestId;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Header carrying the request id, on requests and responses.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("request_id");
+
+/// Request id field.
+pub const REQUEST_ID: &str = "request_id";
+/// Trace id field.
+pub const TRACE_ID: &str = "trace_id";
+/// Span id field, of the root request span.
+pub const SPAN_ID: &str = "span_id";
+/// Fields stored by [correlate].
+pub const FIELDS: [&str; 3] = [REQUEST_ID, TRACE_ID, SPAN_ID];
+
+/// Middleware function inserting the [RequestId] of the request, and the
+/// trace and span ids of the current (request) span, into the span's
+/// storage.
+///
+/// Must run within the request span, after the trace context has been
+/// extracted, e.g. by
+/// [OtelAxumLayer](axum_tracing_opentelemetry::middleware::OtelAxumLayer).
+pub async fn correlate(request: Request, next: Next) -> Response {
+    let span = Span::current();
+    let mut fields = Vec::with_capacity(3);
+
+    if let Some(request_id) = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+    {
+        fields.push((REQUEST_ID, request_id.to_string()));
+    }
+
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        fields.push((TRACE_ID, span_context.trace_id().to_string()));
+        fields.push((SPAN_ID, span_context.span_id().to_string()));
+    }
+
+    storage_layer::insert_fields(&span, fields);
+    next.run(request).await
+}
+
+/// Request id of the current request, as stored by [correlate].
+pub fn request_id() -> Option<String> {
+    storage_layer::field(&Span::current(), REQUEST_ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        middleware::{logging::Logger, request_ulid::MakeRequestUlid},
+        tracing_layers::{format_layer::LogFmtLayer, storage_layer::StorageLayer},
+    };
+    use axum::{body::Body, routing::get, Router};
+    use axum_tracing_opentelemetry::middleware::OtelAxumLayer;
+    use opentelemetry::trace::TracerProvider as _;
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+    use tower::ServiceExt;
+    use tower_http::request_id::SetRequestIdLayer;
+    use tracing::Instrument;
+    use tracing_subscriber::{filter::LevelFilter, prelude::*};
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_request_and_trace_ids() {
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let storage_layer = StorageLayer::default();
+        let correlation_fields = storage_layer.reader(FIELDS);
+        // Logging warnings only, which excludes the request span.
+        let subscriber = tracing_subscriber::registry()
+            .with(storage_layer)
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                LogFmtLayer::new(move || make_writer.clone())
+                    .with_storage_reader(correlation_fields)
+                    .with_filter(LevelFilter::WARN),
+            );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    tracing::warn!("handled");
+                    tracing::info_span!("child").in_scope(|| tracing::warn!("handled"));
+                    request_id().unwrap_or_default()
+                }),
+            )
+            .layer(axum::middleware::from_fn(correlate))
+            .layer(OtelAxumLayer::default())
+            .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUlid));
+
+        let res = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let request_id = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(request_id.len(), 26);
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        for line in lines {
+            assert!(line.starts_with("level=warn msg=handled"));
+            assert!(line.contains(&format!(" request_id={request_id}")));
+            let value = |key: &str| {
+                line.split(&format!(" {key}="))
+                    .nth(1)
+                    .and_then(|rest| rest.split(' ').next())
+                    .unwrap_or_default()
+            };
+            assert_eq!(value(TRACE_ID).len(), 32);
+            assert_eq!(value(SPAN_ID).len(), 16);
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_request_id_to_client_requests() {
+        use wiremock::{
+            matchers::{header, method},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let subscriber = tracing_subscriber::registry().with(StorageLayer::default());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header(
+                REQUEST_ID_HEADER.as_str(),
+                "01ARZ3NDEKTSV4RRFFQ69G5FAV",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(Logger)
+            .build();
+        let span = tracing::info_span!("request");
+        storage_layer::insert_fields(&span, [(REQUEST_ID, "01ARZ3NDEKTSV4RRFFQ69G5FAV")]);
+
+        let res = client
+            .get(server.uri())
+            .send()
+            .instrument(span)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+    }
+}
@@ -1,11 +1,15 @@
 //! Middleware for logging requests/responses for server and client calls.
 
-use crate::{error::AppError, middleware::request_ext::RequestExt, settings::AppEnvironment};
+use crate::{
+    error::AppError,
+    middleware::{correlation, debug_request, redact::redactor, request_ext::RequestExt},
+    settings::AppEnvironment,
+};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use axum::{
     body::{Body, Bytes},
-    http::{Extensions, Request, StatusCode},
+    http::{Extensions, HeaderValue, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -48,16 +52,18 @@ pub trait RequestResponseLogger {
     async fn log_request(request: Request<Body>) -> Result<Request<Body>, AppError> {
         let path = request.path();
         let (parts, body) = request.into_parts();
+        let query_string = parts.uri.query().map(|q| redactor().redact_query(q));
 
         debug!(
             subject = "request",
             category="http.request",
             msg = "started processing request",
             request_path = %path,
-            query_string = parts.uri.query());
+            query_string = query_string.as_deref());
 
         let bytes = buffer("Request", body).await?;
         if let Ok(body) = std::str::from_utf8(&bytes) {
+            let body = redactor().redact_body(body);
             debug!(subject="request", category="http.request", body=?body, request_path=%path);
         }
         let req = Request::from_parts(parts, Body::from(bytes));
@@ -70,7 +76,7 @@ pub trait RequestResponseLogger {
         path: String,
     ) -> Result<Response<Body>, AppError> {
         let status_code = response.status().as_u16();
-        let headers = response.headers().clone();
+        let headers = redactor().redact_headers(response.headers());
 
         match status_code {
             200..=299 => {
@@ -103,6 +109,7 @@ pub trait RequestResponseLogger {
 
         let bytes = buffer("Response", body).await?;
         if let Ok(body) = std::str::from_utf8(&bytes) {
+            let body = redactor().redact_body(body);
             debug!(subject="response", category="http.response", body=?body, request_path=%path);
         }
         let res = Response::from_parts(parts, Body::from(bytes));
@@ -118,6 +125,7 @@ impl RequestResponseLogger for Logger {
     async fn log_request(request: Request<Body>) -> Result<Request<Body>, AppError> {
         let path = request.path();
         let (parts, body) = request.into_parts();
+        let query_string = parts.uri.query().map(|q| redactor().redact_query(q));
 
         match parts.extensions.get::<AppEnvironment>() {
             Some(&AppEnvironment::Local) | Some(&AppEnvironment::Dev) => info!(
@@ -129,12 +137,8 @@ impl RequestResponseLogger for Logger {
                         .get(REQUEST_ID)
                         .map(|h| h.to_str().unwrap_or(NULL)),
                     request_path = %path,
-                    query_string = parts.uri.query(),
-                    authorization = parts
-                        .headers
-                        .get(http::header::AUTHORIZATION.as_str())
-                        .map(|h| h.to_str().unwrap_or(NULL))
-                    .unwrap_or(NULL)),
+                    query_string = query_string.as_deref(),
+                    request_headers = ?redactor().reveal_headers(&parts.headers)),
             _ => {
                 info!(
                     subject = "request",
@@ -145,22 +149,15 @@ impl RequestResponseLogger for Logger {
                         .get(REQUEST_ID)
                         .map(|h| h.to_str().unwrap_or(NULL)),
                     request_path = %path,
-                    query_string = parts.uri.query(),
-                    authorization= parts
-                        .headers
-                        .get(http::header::AUTHORIZATION.as_str())
-                        .map(|h| if h.is_sensitive() {
-                            "<redacted>"
-                        }  else {
-                            h.to_str().unwrap_or(NULL)
-                        })
-                    .unwrap_or(NULL))
+                    query_string = query_string.as_deref(),
+                    request_headers = ?redactor().redact_headers(&parts.headers))
             }
         };
 
         let bytes = buffer("Request", body).await?;
 
         if let Ok(body) = std::str::from_utf8(&bytes) {
+            let body = redactor().redact_body(body);
             debug!(subject="request", category="http.request", body=?body);
         }
 
@@ -173,10 +170,29 @@ impl RequestResponseLogger for Logger {
 impl ReqwestMiddleware for Logger {
     async fn handle(
         &self,
-        request: reqwest::Request,
+        mut request: reqwest::Request,
         extensions: &mut Extensions,
         next: reqwest_middleware::Next<'_>,
     ) -> Result<reqwest::Response, reqwest_middleware::Error> {
+        // Forward the signed debug header of an elevated request to
+        // allowlisted internal hosts, for end-to-end debugging.
+        if let Some(debug_request) = debug_request::current() {
+            if let Some((name, value)) = debug_request.forward_header(request.url()) {
+                let mut value = value.clone();
+                value.set_sensitive(true);
+                request.headers_mut().insert(name.clone(), value);
+            }
+        }
+        // Forward the id of the request being handled, for correlation.
+        if let Some(request_id) = correlation::request_id() {
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                request
+                    .headers_mut()
+                    .entry(correlation::REQUEST_ID_HEADER)
+                    .or_insert(value);
+            }
+        }
+        redactor().mark_sensitive(request.headers_mut());
         log_reqwest(&request, extensions);
         let url = request.url().clone();
         let _ = extensions.insert(url);
@@ -210,6 +226,8 @@ fn log_reqwest(request: &reqwest::Request, extensions: &mut Extensions) {
         .map(|h| h.to_str().unwrap_or(NULL))
         .unwrap_or(NULL);
     let host = request.url().host_str().unwrap_or(host_hdr);
+    let url = redactor().redact_url(request.url());
+    let query_string = request.url().query().map(|q| redactor().redact_query(q));
 
     match extensions.get::<AppEnvironment>() {
         Some(&AppEnvironment::Local) | Some(&AppEnvironment::Dev) => {
@@ -217,17 +235,13 @@ fn log_reqwest(request: &reqwest::Request, extensions: &mut Extensions) {
                 subject = "client.request",
                 category="http.request",
                 client.method = %request.method(),
-                client.url = %request.url(),
+                client.url = %url,
                 client.host = host,
                 client.request_path = request.url().path(),
-                client.query_string = request.url().query(),
+                client.query_string = query_string.as_deref(),
                 client.user_agent = user_agent,
                 client.version = ?request.version(),
-                client.authorization= request
-                    .headers()
-                    .get(http::header::AUTHORIZATION.as_str())
-                    .map(|h| h.to_str().unwrap_or(NULL))
-                    .unwrap_or(NULL),
+                client.request_headers = ?redactor().reveal_headers(request.headers()),
                 "started processing client request")
         }
         _ => {
@@ -235,20 +249,26 @@ fn log_reqwest(request: &reqwest::Request, extensions: &mut Extensions) {
                 subject = "client.request",
                 category="http.request",
                 client.method = %request.method(),
-                client.url = %request.url(),
+                client.url = %url,
                 client.host = host,
                 client.request_path = request.url().path(),
-                client.query_string = request.url().query(),
+                client.query_string = query_string.as_deref(),
                 client.user_agent = user_agent,
                 client.version = ?request.version(),
-                client.authorization= request
-                    .headers()
-                    .get(http::header::AUTHORIZATION.as_str())
-                    .map(|_h| "<redacted>")
-                    .unwrap_or(NULL),
+                client.request_headers = ?redactor().redact_headers(request.headers()),
                 "started processing client request")
         }
     }
+    if debug_request::elevated_level().is_some() {
+        if let Some(body) = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+        {
+            let body = redactor().redact_body(body);
+            debug!(subject = "client.request", category = "http.request", body = ?body, client.url = %url);
+        }
+    }
 }
 
 async fn log_reqwest_response(
@@ -288,20 +308,23 @@ async fn log_reqwest_response(
         .ok_or_else(|| anyhow!("failed to find Url extension"))?;
 
     let status_code = response.status().as_u16();
+    let redacted_url = redactor().redact_url(url);
 
     let post_log_response = match status_code {
         400..=599 => {
             let version = response.version();
             let headers = response.headers().clone();
+            let logged_headers = redactor().redact_headers(&headers);
             let bytes = response.bytes().await?;
             if let Ok(body) = std::str::from_utf8(&bytes) {
+                let body = redactor().redact_body(body);
                 warn!(
                     subject = "client.response",
                     category="http.response",
                     body = ?body,
                     client.status = ?status_code,
-                    client.response_headers = ?headers,
-                    client.url = %url,
+                    client.response_headers = ?logged_headers,
+                    client.url = %redacted_url,
                     client.request_path = url.path(),
                     "error while processing client request");
             }
@@ -309,6 +332,29 @@ async fn log_reqwest_response(
             let body = reqwest::Body::from(bytes);
             into_reqwest_response(body, headers, status_code, version).await?
         }
+        // Bodies of successful responses are only logged within debug
+        // requests, as buffering them isn't free.
+        _ if debug_request::elevated_level().is_some() => {
+            let version = response.version();
+            let headers = response.headers().clone();
+            let logged_headers = redactor().redact_headers(&headers);
+            let bytes = response.bytes().await?;
+            if let Ok(body) = std::str::from_utf8(&bytes) {
+                let body = redactor().redact_body(body);
+                debug!(
+                    subject = "client.response",
+                    category="http.response",
+                    body = ?body,
+                    client.status = ?status_code,
+                    client.response_headers = ?logged_headers,
+                    client.url = %redacted_url,
+                    client.request_path = url.path(),
+                    "finished processing client request");
+            }
+
+            let body = reqwest::Body::from(bytes);
+            into_reqwest_response(body, headers, status_code, version).await?
+        }
         _ => response,
     };
 
@@ -326,7 +372,7 @@ fn log_reqwest_error(error: &reqwest::Error, extensions: &mut Extensions) -> Res
         client.error = format!("{:#?}", error.to_string()),
         client.request_path = url.path(),
         client.status = ?error.status().map(|status_code| status_code.as_u16().to_string()).unwrap_or_else(|| NONE.to_string()),
-        client.url = %url,
+        client.url = %redactor().redact_url(url),
         "error processing client request");
 
     Ok(())
@@ -341,7 +387,7 @@ fn log_middleware_error(error: &anyhow::Error, extensions: &mut Extensions) -> R
         subject = "client.response",
         category="http.response",
         error = format!("{:#?}", error.to_string()),
-        client.url = %url,
+        client.url = %redactor().redact_url(url),
         client.request_path = url.path(),
         client.status = NONE,
         "error processing client request within {{project-name}} middleware");
@@ -1,9 +1,15 @@
 //! Additional [axum::middleware].
 
+pub mod admin_auth;
 pub mod client;
+pub mod correlation;
+pub mod debug_request;
 pub mod logging;
 pub mod metrics;
+pub mod propagation;
+pub mod redact;
 pub(crate) mod request_ext;
+pub mod request_id;
 pub mod request_ulid;
 pub mod reqwest_retry;
 pub mod reqwest_tracing;
@@ -0,0 +1,351 @@
+//! Redaction of sensitive headers and fields in logged request/response
+//! bodies and query strings.
+//!
+//! Rules are configured via [settings::Redaction](crate::settings::Redaction)
+//! and installed once at startup with [init]. Both the server
+//! [RequestResponseLogger](crate::middleware::logging::RequestResponseLogger)
+//! and the reqwest [Logger](crate::middleware::logging::Logger) middleware
+//! run payloads through the installed [Redactor] before emitting them.
+
+use crate::settings::Redaction;
+use anyhow::Result;
+use http::{
+    header::{
+        Entry, HeaderMap, HeaderName, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE,
+    },
+    HeaderValue,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use serde_json::Value;
+use std::borrow::Cow;
+use url::{form_urlencoded, Position};
+
+/// Replacement value for redacted fields.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Credential headers, redacted in every environment.
+const CREDENTIAL_HEADERS: [HeaderName; 4] =
+    [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+static REDACTOR: OnceCell<Redactor> = OnceCell::new();
+
+static DEFAULT_REDACTOR: Lazy<Redactor> =
+    Lazy::new(|| Redactor::new(&Redaction::default()).expect("default redaction rules are valid"));
+
+/// Install the process-wide [Redactor] used by logging middleware.
+///
+/// Only the first call takes effect; later calls return the rules back as an
+/// error.
+pub fn init(redactor: Redactor) -> Result<(), Box<Redactor>> {
+    REDACTOR.set(redactor).map_err(Box::new)
+}
+
+/// Installed [Redactor], or one built from the default [Redaction] rules if
+/// [init] was never called.
+pub(crate) fn redactor() -> &'static Redactor {
+    REDACTOR.get().unwrap_or(&DEFAULT_REDACTOR)
+}
+
+/// Applies [Redaction] rules to payloads before they are logged.
+#[derive(Debug)]
+pub struct Redactor {
+    headers: Vec<HeaderName>,
+    reveal_headers: Vec<HeaderName>,
+    json_pointers: Vec<String>,
+    fields: RegexSet,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Compile [Redaction] rules.
+    ///
+    /// Field-name patterns are matched case-insensitively against the whole
+    /// name, with `*` matching any run of characters.
+    pub fn new(settings: &Redaction) -> Result<Self> {
+        let headers = settings
+            .headers
+            .iter()
+            .map(|h| HeaderName::try_from(h.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let reveal_headers = settings
+            .reveal_headers
+            .iter()
+            .map(|h| HeaderName::try_from(h.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fields = RegexSetBuilder::new(settings.fields.iter().map(|f| glob_to_regex(f)))
+            .case_insensitive(true)
+            .build()?;
+
+        let patterns = settings
+            .patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            headers,
+            reveal_headers,
+            json_pointers: settings.json_pointers.clone(),
+            fields,
+            patterns,
+        })
+    }
+
+    /// Sensitive header names.
+    pub fn sensitive_headers(&self) -> &[HeaderName] {
+        &self.headers
+    }
+
+    /// Mark sensitive headers as such, e.g. for outbound requests, so
+    /// that their values are hidden from `Debug` output and never indexed by
+    /// HPACK.
+    pub fn mark_sensitive(&self, headers: &mut HeaderMap) {
+        for name in &self.headers {
+            if let Entry::Occupied(mut entry) = headers.entry(name) {
+                entry.iter_mut().for_each(|value| value.set_sensitive(true));
+            }
+        }
+    }
+
+    /// Copy of `headers` for logging, with the values of sensitive and
+    /// credential headers (and any value already marked sensitive) replaced.
+    pub fn redact_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        for (name, value) in headers.iter_mut() {
+            if self.is_sensitive(name, value) {
+                *value = HeaderValue::from_static(REDACTED);
+            }
+        }
+        headers
+    }
+
+    /// Copy of `headers` for logging in local/dev environments, as
+    /// [Redactor::redact_headers], but showing the values of allowlisted
+    /// sensitive headers in plain text. Credential headers stay redacted.
+    pub fn reveal_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        for (name, value) in headers.iter_mut() {
+            if self.reveal_headers.contains(name) && !CREDENTIAL_HEADERS.contains(name) {
+                value.set_sensitive(false);
+            } else if self.is_sensitive(name, value) {
+                *value = HeaderValue::from_static(REDACTED);
+            }
+        }
+        headers
+    }
+
+    fn is_sensitive(&self, name: &HeaderName, value: &HeaderValue) -> bool {
+        value.is_sensitive() || self.headers.contains(name) || CREDENTIAL_HEADERS.contains(name)
+    }
+
+    /// Redact a request/response body.
+    ///
+    /// JSON bodies have matching pointers and field names replaced, with
+    /// regex patterns applied to the remaining string values. Any other body
+    /// is treated as free text and only has the regex patterns applied.
+    pub fn redact_body<'a>(&self, body: &'a str) -> Cow<'a, str> {
+        match serde_json::from_str::<Value>(body) {
+            Ok(mut json) if json.is_object() || json.is_array() => {
+                self.redact_json(&mut json);
+                Cow::Owned(json.to_string())
+            }
+            _ => self.redact_text(body),
+        }
+    }
+
+    /// Redact the values of query string parameters whose (percent-decoded)
+    /// keys match a field-name rule, applying regex patterns to the rest.
+    pub fn redact_query<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        if self.fields.is_empty() && self.patterns.is_empty() {
+            return Cow::Borrowed(query);
+        }
+
+        let redacted = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.fields.is_match(&decode_key(key)) => {
+                    format!("{key}={REDACTED}")
+                }
+                Some((key, value)) => format!("{key}={}", self.redact_text(value)),
+                None => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        Cow::Owned(redacted)
+    }
+
+    /// Redact the query string portion of a [reqwest::Url].
+    pub fn redact_url(&self, url: &reqwest::Url) -> String {
+        match url.query() {
+            Some(query) => format!(
+                "{}?{}{}",
+                &url[..Position::AfterPath],
+                self.redact_query(query),
+                &url[Position::AfterQuery..]
+            ),
+            None => url.to_string(),
+        }
+    }
+
+    fn redact_json(&self, json: &mut Value) {
+        for pointer in &self.json_pointers {
+            if let Some(value) = json.pointer_mut(pointer) {
+                *value = Value::from(REDACTED);
+            }
+        }
+
+        self.redact_json_fields(json);
+    }
+
+    fn redact_json_fields(&self, json: &mut Value) {
+        match json {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.fields.is_match(key) {
+                        *value = Value::from(REDACTED);
+                    } else {
+                        self.redact_json_fields(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_json_fields(v)),
+            Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact_text(s) {
+                    *s = redacted;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.patterns
+            .iter()
+            .fold(Cow::Borrowed(text), |text, pattern| {
+                match pattern.replace_all(&text, REDACTED) {
+                    Cow::Borrowed(_) => text,
+                    Cow::Owned(replaced) => Cow::Owned(replaced),
+                }
+            })
+    }
+}
+
+/// Convert a `*`-wildcard field-name pattern into an anchored regex.
+fn glob_to_regex(glob: &str) -> String {
+    let pattern = glob
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    format!("^{pattern}$")
+}
+
+/// Percent-decoded query parameter `key`, with `+` as a space.
+fn decode_key(key: &str) -> Cow<'_, str> {
+    form_urlencoded::parse(key.as_bytes())
+        .next()
+        .map_or(Cow::Borrowed(key), |(key, _)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Redactor {
+        Redactor::new(&Redaction {
+            headers: vec!["authorization".to_string(), "x-api-key".to_string()],
+            json_pointers: vec!["/user/ssn".to_string()],
+            fields: vec![
+                "password".to_string(),
+                "*token*".to_string(),
+                "api_key".to_string(),
+            ],
+            patterns: vec![r"\b\d{4}-\d{4}-\d{4}-\d{4}\b".to_string()],
+            reveal_headers: vec!["authorization".to_string(), "x-api-key".to_string()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn redacts_sensitive_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer t"));
+        headers.insert("x-api-key", HeaderValue::from_static("k"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        let mut cookie = HeaderValue::from_static("session=s");
+        cookie.set_sensitive(true);
+        headers.insert("cookie", cookie);
+
+        let redacted = rules().redact_headers(&headers);
+        assert_eq!(redacted["authorization"], REDACTED);
+        assert_eq!(redacted["x-api-key"], REDACTED);
+        assert_eq!(redacted["cookie"], REDACTED);
+        assert_eq!(redacted["accept"], "*/*");
+
+        rules().mark_sensitive(&mut headers);
+        assert!(headers["authorization"].is_sensitive());
+        assert!(headers["x-api-key"].is_sensitive());
+        assert!(!headers["accept"].is_sensitive());
+
+        // Only allowlisted headers are revealed, and never credentials.
+        let revealed = rules().reveal_headers(&headers);
+        assert_eq!(revealed["authorization"], REDACTED);
+        assert_eq!(revealed["cookie"], REDACTED);
+        assert_eq!(format!("{:?}", revealed["x-api-key"]), "\"k\"");
+        assert_eq!(revealed["accept"], "*/*");
+    }
+
+    #[test]
+    fn rejects_invalid_header_names() {
+        assert!(Redactor::new(&Redaction {
+            headers: vec!["not a header".to_string()],
+            ..Redaction::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn redacts_json_fields_pointers_and_patterns() {
+        let body = r#"{"user":{"name":"a","ssn":"123","Password":"p"},"items":[{"access_token":"t"}],"note":"card 1234-5678-9012-3456"}"#;
+        let redacted: Value = serde_json::from_str(&rules().redact_body(body)).unwrap();
+
+        assert_eq!(redacted["user"]["name"], "a");
+        assert_eq!(redacted["user"]["ssn"], REDACTED);
+        assert_eq!(redacted["user"]["Password"], REDACTED);
+        assert_eq!(redacted["items"][0]["access_token"], REDACTED);
+        assert_eq!(redacted["note"], format!("card {REDACTED}"));
+    }
+
+    #[test]
+    fn redacts_text_bodies_with_patterns() {
+        assert_eq!(
+            rules().redact_body("paid with 1234-5678-9012-3456"),
+            format!("paid with {REDACTED}")
+        );
+        assert_eq!(rules().redact_body("nothing here"), "nothing here");
+    }
+
+    #[test]
+    fn redacts_query_strings() {
+        assert_eq!(
+            rules().redact_query("user=a&password=b&refresh_token=c&flag"),
+            format!("user=a&password={REDACTED}&refresh_token={REDACTED}&flag")
+        );
+        assert_eq!(
+            rules().redact_query("pass%77ord=b&api%5Fkey=c&%70assword&q=a%20b"),
+            format!("pass%77ord={REDACTED}&api%5Fkey={REDACTED}&%70assword&q=a%20b")
+        );
+    }
+
+    #[test]
+    fn redacts_url_queries() {
+        let url = reqwest::Url::parse("http://localhost/path?password=b&q=1").unwrap();
+        assert_eq!(
+            rules().redact_url(&url),
+            format!("http://localhost/path?password={REDACTED}&q=1")
+        );
+    }
+}
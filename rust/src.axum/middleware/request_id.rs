@@ -0,0 +1,294 @@
+//! Middleware accepting request ids sent by callers or upstream proxies, as
+//! allowed by [settings::RequestIdPolicy].
+//!
+//! [SetRequestId](tower_http::request_id::SetRequestId) keeps any id found
+//! in its header, and otherwise generates one with
+//! [MakeRequestUlid](crate::middleware::request_ulid::MakeRequestUlid). This
+//! middleware runs before it, copying a trusted, valid incoming id into that
+//! header, and removing an untrusted one from it.
+
+use crate::{middleware::correlation::REQUEST_ID_HEADER, settings};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use http::{HeaderName, HeaderValue};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::debug;
+use ulid::Ulid;
+use uuid::Uuid;
+
+/// Validates incoming request ids against a [settings::RequestIdPolicy].
+#[derive(Debug)]
+pub struct RequestIds {
+    trust_incoming: bool,
+    headers: Vec<HeaderName>,
+    formats: Vec<settings::RequestIdFormat>,
+    max_length: usize,
+    trusted_sources: Vec<IpNet>,
+}
+
+impl RequestIds {
+    /// Create [RequestIds] from a [settings::RequestIdPolicy], failing on an
+    /// invalid header name or trusted source, or on trusting incoming ids
+    /// without trusted sources.
+    pub fn new(settings: &settings::RequestIdPolicy) -> Result<Self> {
+        if settings.trust_incoming && settings.trusted_sources.is_empty() {
+            return Err(anyhow!(
+                "trusted request id sources required to trust incoming request ids"
+            ));
+        }
+        let headers = settings
+            .headers
+            .iter()
+            .map(|header| {
+                HeaderName::from_str(header)
+                    .map_err(|err| anyhow!("invalid request id header {header}: {err}"))
+            })
+            .collect::<Result<_>>()?;
+        let trusted_sources = settings
+            .trusted_sources
+            .iter()
+            .map(|source| {
+                IpNet::from_str(source)
+                    .or_else(|_| IpAddr::from_str(source).map(IpNet::from))
+                    .map_err(|err| anyhow!("invalid trusted request id source {source}: {err}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            trust_incoming: settings.trust_incoming,
+            headers,
+            formats: settings.formats.clone(),
+            max_length: settings.max_length,
+            trusted_sources,
+        })
+    }
+
+    /// Whether request ids sent by `peer` are trusted.
+    pub fn is_trusted(&self, peer: Option<IpAddr>) -> bool {
+        self.trust_incoming
+            && peer.map_or(false, |peer| {
+                self.trusted_sources
+                    .iter()
+                    .any(|source| source.contains(&peer))
+            })
+    }
+
+    /// Whether `id` is within the maximum length, and of an accepted format.
+    pub fn is_valid(&self, id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= self.max_length
+            && self.formats.iter().any(|format| match format {
+                settings::RequestIdFormat::Ulid => Ulid::from_string(id).is_ok(),
+                settings::RequestIdFormat::Uuid => Uuid::try_parse(id).is_ok(),
+                settings::RequestIdFormat::Any => id.bytes().all(|b| b.is_ascii_graphic()),
+            })
+    }
+
+    /// First valid request id in the request's headers, if sent by a trusted
+    /// peer.
+    pub fn accepted<B>(&self, request: &http::Request<B>) -> Option<HeaderValue> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if !self.is_trusted(peer) {
+            return None;
+        }
+
+        self.headers
+            .iter()
+            .filter_map(|header| request.headers().get(header))
+            .find(|value| value.to_str().map_or(false, |id| self.is_valid(id)))
+            .cloned()
+    }
+
+    fn has_incoming<B>(&self, request: &http::Request<B>) -> bool {
+        self.headers
+            .iter()
+            .chain([&REQUEST_ID_HEADER])
+            .any(|header| request.headers().contains_key(header))
+    }
+}
+
+/// Middleware function setting the request id header to the accepted
+/// incoming id, or removing it, for a ULID to be generated. Ignored ids are
+/// logged.
+pub async fn accept(
+    State(ids): State<Arc<RequestIds>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match ids.accepted(&request) {
+        Some(id) => {
+            request.headers_mut().insert(REQUEST_ID_HEADER, id);
+        }
+        None => {
+            if ids.has_incoming(&request) {
+                debug!(
+                    subject = "request_id",
+                    category = "http.request",
+                    "ignoring untrusted or invalid request id"
+                );
+            }
+            request.headers_mut().remove(REQUEST_ID_HEADER);
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract::request_id::RequestId, middleware::request_ulid::MakeRequestUlid};
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::request_id::{PropagateRequestIdLayer, SetRequestIdLayer};
+
+    const ULID: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const UUID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    const ANY: [&str; 2] = ["0.0.0.0/0", "::/0"];
+
+    fn request_ids(trusted_sources: &[&str]) -> RequestIds {
+        RequestIds::new(&settings::RequestIdPolicy {
+            trust_incoming: true,
+            trusted_sources: trusted_sources.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn validates_format_and_length() {
+        let ids = request_ids(&ANY);
+        assert!(ids.is_valid(ULID));
+        assert!(ids.is_valid(&ULID.to_lowercase()));
+        assert!(ids.is_valid(UUID));
+        assert!(!ids.is_valid(""));
+        assert!(!ids.is_valid("not-a-request-id"));
+
+        let ids = RequestIds::new(&settings::RequestIdPolicy {
+            formats: vec![settings::RequestIdFormat::Any],
+            max_length: 8,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(ids.is_valid("abc-123"));
+        assert!(!ids.is_valid("abc 123"));
+        assert!(!ids.is_valid("abcd-1234"));
+    }
+
+    #[test]
+    fn trusts_allowlisted_sources() {
+        let ids = request_ids(&["10.0.0.0/8", "::1"]);
+        assert!(ids.is_trusted(Some("10.1.2.3".parse().unwrap())));
+        assert!(ids.is_trusted(Some("::1".parse().unwrap())));
+        assert!(!ids.is_trusted(Some("192.168.0.1".parse().unwrap())));
+        assert!(!ids.is_trusted(None));
+
+        assert!(request_ids(&ANY).is_trusted(Some("192.168.0.1".parse().unwrap())));
+        assert!(!request_ids(&ANY).is_trusted(None));
+        assert!(!RequestIds::new(&settings::RequestIdPolicy::default())
+            .unwrap()
+            .is_trusted(None));
+        assert!(RequestIds::new(&settings::RequestIdPolicy {
+            trusted_sources: vec!["10.0.0.0/33".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+
+        // Trusting any peer must be explicit.
+        assert!(RequestIds::new(&settings::RequestIdPolicy {
+            trust_incoming: true,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn accepts_trusted_valid_request_ids() {
+        let app = |ids: RequestIds| {
+            Router::new()
+                .route("/", get(|RequestId(id): RequestId| async move { id }))
+                .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+                .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUlid))
+                .layer(axum::middleware::from_fn_with_state(Arc::new(ids), accept))
+        };
+
+        for (ids, peer, header, id, accepted) in [
+            (
+                request_ids(&ANY),
+                Some("10.0.0.1"),
+                "x-request-id",
+                UUID,
+                true,
+            ),
+            (request_ids(&ANY), Some("::1"), "request_id", ULID, true),
+            (
+                request_ids(&ANY),
+                Some("10.0.0.1"),
+                "x-request-id",
+                "invalid",
+                false,
+            ),
+            (request_ids(&ANY), None, "x-request-id", UUID, false),
+            (
+                request_ids(&["10.0.0.1"]),
+                Some("10.0.0.1"),
+                "x-request-id",
+                ULID,
+                true,
+            ),
+            (
+                request_ids(&["10.0.0.1"]),
+                Some("10.0.0.2"),
+                "request_id",
+                ULID,
+                false,
+            ),
+            (
+                RequestIds::new(&settings::RequestIdPolicy::default()).unwrap(),
+                None,
+                "request_id",
+                ULID,
+                false,
+            ),
+        ] {
+            let mut request = Request::builder()
+                .uri("/")
+                .header(header, id)
+                .body(Body::empty())
+                .unwrap();
+            if let Some(peer) = peer {
+                let addr = SocketAddr::new(peer.parse().unwrap(), 443);
+                request.extensions_mut().insert(ConnectInfo(addr));
+            }
+
+            let res = app(ids).oneshot(request).await.unwrap();
+            let response_id = res.headers()[&REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, response_id);
+
+            if accepted {
+                assert_eq!(response_id, id);
+            } else {
+                assert_ne!(response_id, id);
+                assert!(Ulid::from_string(&response_id).is_ok());
+            }
+        }
+    }
+}
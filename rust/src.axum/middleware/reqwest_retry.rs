@@ -4,10 +4,14 @@
 //! [TrueLayer's request-retry middleware]:
 //! <https://github.com/TrueLayer/reqwest-middleware/blob/main/reqwest-retry/src/middleware.rs>
 
-use crate::middleware::client;
+use crate::{
+    metrics::{cardinality, registry::MetricDescription},
+    middleware::client,
+};
 use anyhow::anyhow;
 use chrono::Utc;
 use http::Extensions;
+use metrics::Unit;
 use reqwest::{Request, Response};
 use reqwest_middleware::{Error, Middleware, Next, Result};
 use reqwest_retry::{RetryPolicy, Retryable};
@@ -18,6 +22,15 @@ use tracing::warn;
 /// We limit the number of retries to a maximum of `10` to avoid stack-overflow issues due to the recursion.
 static MAXIMUM_NUMBER_OF_RETRIES: u32 = 10;
 
+/// Descriptions of metrics recorded by [RetryTransientMiddleware].
+pub(crate) fn descriptions() -> Vec<MetricDescription> {
+    vec![MetricDescription::counter(
+        "client_http_requests_retry_total",
+        "Outbound HTTP client request retries.",
+    )
+    .with_unit(Unit::Count)]
+}
+
 /// `RetryTransientMiddleware` offers retry logic for requests that fail in a transient manner
 /// and can be safely executed again.
 ///
@@ -163,7 +176,11 @@ impl<T: RetryPolicy + Send + Sync> RetryTransientMiddleware<T> {
         extensions: &mut Extensions,
         next: Next<'a>,
     ) -> Result<Response> {
-        let request_path = request.url().path().to_string();
+        let request_path = cardinality::guard().label(
+            "client_http_requests_retry_total",
+            "request_path",
+            request.url().path().to_string(),
+        );
         let method = request.method().clone();
 
         let result = next.run(request, extensions).await;
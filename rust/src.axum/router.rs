@@ -1,8 +1,16 @@
 //! Main [axum::Router] interface for webserver.
 
 use crate::{
-    middleware::logging::{log_request_response, DebugOnlyLogger, Logger},
-    routes::{fallback::notfound_404, health, ping},
+    middleware::{
+        admin_auth,
+        logging::{log_request_response, DebugOnlyLogger, Logger},
+    },
+    routes::{
+        admin::{self, AdminState},
+        fallback::notfound_404,
+        health, metrics, ping,
+    },
+    settings::AdminAuth,
 };
 use axum::{routing::get, Router};
 
@@ -22,3 +30,33 @@ pub fn setup_app_router() -> Router {
 
     Router::merge(router, healthcheck_router)
 }
+
+/// Setup admin router, served on the metrics port, authenticating all
+/// requests if `auth` is set.
+///
+/// Mutating routes (`PUT /log-level`) are only served with `auth` set, and
+/// answer `405 Method Not Allowed` otherwise.
+pub fn setup_admin_router(state: AdminState, auth: Option<AdminAuth>) -> Router {
+    let log_level = match auth {
+        Some(_) => get(admin::log_level).put(admin::set_log_level),
+        None => get(admin::log_level),
+    };
+
+    let mut router = Router::new()
+        .route("/metrics", get(admin::metrics))
+        .route("/metrics/catalog", get(metrics::catalog))
+        .route("/build-info", get(admin::build_info))
+        .route("/settings", get(admin::settings))
+        .route("/log-level", log_level)
+        .fallback(notfound_404)
+        .with_state(state);
+
+    if let Some(auth) = auth {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            auth,
+            admin_auth::authenticate,
+        ));
+    }
+
+    router
+}
@@ -1,5 +1,7 @@
 //! Routes for [axum::Router].
 
+pub mod admin;
 pub mod fallback;
 pub mod health;
+pub mod metrics;
 pub mod ping;
@@ -2,14 +2,18 @@
 
 use config::{Config, ConfigError, Environment, File};
 use http::Uri;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_with::serde_as;
-use std::{path::PathBuf, time::Duration};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// Placeholder for secrets in logged or served settings.
+const REDACTED: &str = "<redacted>";
 
 /// Names of environments for {{project-name}}.
 /// Overrides serialization to force lower case in settings and
 /// environment variables
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum AppEnvironment {
     /// Local environment (local testing).
@@ -30,7 +34,7 @@ impl std::fmt::Display for AppEnvironment {
 }
 
 /// Server settings.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Server {
     /// Server [AppEnvironment].
     pub environment: AppEnvironment,
@@ -40,48 +44,792 @@ pub struct Server {
     pub metrics_port: u16,
     /// Server timeout in milliseconds.
     pub timeout_ms: u64,
+    /// Policy for incoming request ids.
+    #[serde(default)]
+    pub request_id: RequestIdPolicy,
+}
+
+/// Policy for request ids sent by callers or upstream proxies. Untrusted or
+/// invalid ids are replaced with a generated ULID.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RequestIdPolicy {
+    /// Trust valid incoming request ids.
+    pub trust_incoming: bool,
+    /// Request headers checked for an incoming id, in order.
+    pub headers: Vec<String>,
+    /// Accepted request id formats.
+    pub formats: Vec<RequestIdFormat>,
+    /// Maximum length of accepted request ids.
+    pub max_length: usize,
+    /// Peer addresses or networks (e.g. `10.0.0.0/8`) trusted to send
+    /// request ids. Required with `trust_incoming`.
+    pub trusted_sources: Vec<String>,
+}
+
+impl Default for RequestIdPolicy {
+    fn default() -> Self {
+        Self {
+            trust_incoming: false,
+            headers: vec!["x-request-id".to_string(), "request_id".to_string()],
+            formats: vec![RequestIdFormat::Ulid, RequestIdFormat::Uuid],
+            max_length: 64,
+            trusted_sources: vec![],
+        }
+    }
+}
+
+/// Format of an accepted request id.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestIdFormat {
+    /// [ULID](https://github.com/ulid/spec).
+    Ulid,
+    /// UUID, in any of its textual forms.
+    Uuid,
+    /// Any visible ASCII value.
+    Any,
 }
 
 /// Process monitoring settings.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Monitoring {
     /// Monitoring collection interval.
     pub process_collector_interval: u64,
+    /// Maximum distinct values per metric label, past which values are
+    /// collapsed into an `overflow` value.
+    #[serde(default = "default_max_label_values")]
+    pub max_label_values: usize,
+    /// Also collect host (CPU, memory, load, network) and container (cgroup
+    /// CPU quota/throttling, memory limit/usage) metrics.
+    #[serde(default)]
+    pub host_metrics: bool,
+}
+
+fn default_max_label_values() -> usize {
+    crate::metrics::cardinality::DEFAULT_MAX_LABEL_VALUES
+}
+
+/// Metric name matchers.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricMatcher {
+    /// Matches the full metric name.
+    Full(String),
+    /// Matches metric names starting with the given prefix.
+    Prefix(String),
+    /// Matches metric names ending with the given suffix.
+    Suffix(String),
+}
+
+/// Histogram buckets for metrics matched by name, e.g.
+/// `{ suffix = "_duration_seconds", buckets = [0.1, 0.5, 1.0] }`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MetricBuckets {
+    /// Metric name matcher.
+    #[serde(flatten)]
+    pub matcher: MetricMatcher,
+    /// Bucket boundaries.
+    pub buckets: Vec<f64>,
+}
+
+/// Metrics settings, applied to the Prometheus recorder.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Metrics {
+    /// Histogram buckets by metric name matcher. Matchers are tried full
+    /// names first, then prefixes, then suffixes.
+    pub buckets: Vec<MetricBuckets>,
+    /// Quantiles of histograms not matched by any bucket matcher, which are
+    /// rendered as native Prometheus summaries.
+    pub quantiles: Vec<f64>,
+    /// Static labels added to every metric, besides `service`, `environment`
+    /// and `version`.
+    pub global_labels: HashMap<String, String>,
+    /// Seconds after which series that haven't been updated are removed from
+    /// the rendered output. Disabled if unset.
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            buckets: vec![MetricBuckets {
+                matcher: MetricMatcher::Suffix("_duration_seconds".to_string()),
+                buckets: crate::metrics::prom::EXPONENTIAL_SECONDS.to_vec(),
+            }],
+            quantiles: vec![0.0, 0.5, 0.9, 0.95, 0.99, 0.999, 1.0],
+            global_labels: HashMap::new(),
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+impl Metrics {
+    /// Convert `idle_timeout_secs` to [Duration].
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+}
+
+/// Log output formats.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// [Logfmt](https://brandur.org/logfmt) key/value pairs.
+    #[default]
+    Logfmt,
+    /// Newline-delimited JSON objects.
+    Json,
+}
+
+/// Logging settings.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Logging {
+    /// Output format for log lines.
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Redaction rules for logged headers, request/response bodies and query
+    /// strings.
+    #[serde(default)]
+    pub redaction: Redaction,
+    /// Per-request log level elevation through a request header.
+    #[serde(default)]
+    pub debug_request: DebugRequest,
+    /// Rate limiting and sampling of log events, per sink.
+    #[serde(default)]
+    pub sampling: LogSampling,
+    /// Log output to stdout.
+    #[serde(default)]
+    pub stdout: StdoutLog,
+    /// Log output to files, additionally or instead of stdout.
+    #[serde(default)]
+    pub files: Vec<LogFile>,
+    /// Span fields promoted into, or skipped from, log lines.
+    #[serde(default)]
+    pub fields: LogFields,
+    /// Span lifecycle events logged, besides events within spans.
+    #[serde(default = "default_span_events")]
+    pub span_events: Vec<SpanEvent>,
+}
+
+impl Logging {
+    /// Combine `span_events` into a [FmtSpan].
+    pub fn fmt_span(&self) -> FmtSpan {
+        self.span_events
+            .iter()
+            .fold(FmtSpan::NONE, |fmt_span, event| {
+                fmt_span | FmtSpan::from(*event)
+            })
+    }
+}
+
+/// Span lifecycle events, as in [FmtSpan].
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpanEvent {
+    /// Span creation.
+    New,
+    /// Every entry into a span.
+    Enter,
+    /// Every exit from a span.
+    Exit,
+    /// Span close, with its busy and idle times.
+    Close,
+    /// Every entry and exit.
+    Active,
+    /// All of the above.
+    Full,
+}
+
+impl From<SpanEvent> for FmtSpan {
+    fn from(event: SpanEvent) -> Self {
+        match event {
+            SpanEvent::New => FmtSpan::NEW,
+            SpanEvent::Enter => FmtSpan::ENTER,
+            SpanEvent::Exit => FmtSpan::EXIT,
+            SpanEvent::Close => FmtSpan::CLOSE,
+            SpanEvent::Active => FmtSpan::ACTIVE,
+            SpanEvent::Full => FmtSpan::FULL,
+        }
+    }
+}
+
+fn default_span_events() -> Vec<SpanEvent> {
+    vec![SpanEvent::New, SpanEvent::Close]
+}
+
+/// Contextual span fields promoted into, or skipped from, log lines, besides
+/// the defaults of
+/// [SpanFields](crate::tracing_layers::format_layer::SpanFields) and
+/// [StorageLayer](crate::tracing_layers::storage_layer::StorageLayer).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogFields {
+    /// Span fields written on every line of spans carrying them, e.g.
+    /// `tenant_id` or `user_id`.
+    pub promote: Vec<String>,
+    /// Span fields skipped on event lines.
+    pub skip: Vec<String>,
+    /// Event fields stored on their span, like `error`, and so logged with
+    /// its later events and close.
+    pub keep_event: Vec<String>,
+}
+
+/// Stdout log output.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StdoutLog {
+    /// Whether to log to stdout.
+    pub enabled: bool,
+    /// [EnvFilter] directive of stdout logs. Follows the runtime log filter
+    /// (`RUST_LOG`, or as changed through the admin server) if unset. A set
+    /// level is fixed: admin `/log-level` changes don't apply to it.
+    ///
+    /// [EnvFilter]: tracing_subscriber::EnvFilter
+    pub level: Option<String>,
+}
+
+impl Default for StdoutLog {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: None,
+        }
+    }
+}
+
+/// Log file rotation policies.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// New file every day.
+    #[default]
+    Daily,
+    /// New file every hour.
+    Hourly,
+    /// New file once the current one exceeds `max_size_bytes`.
+    Size,
+    /// Single file, never rotated.
+    Never,
+}
+
+/// Log file output.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LogFile {
+    /// Directory of the log files, created if missing.
+    pub directory: PathBuf,
+    /// File name prefix, e.g. `app` for `app.2024-01-31.log` with daily
+    /// rotation, or `app.log` and `app.1.log` with size-based rotation.
+    pub prefix: String,
+    /// Rotation policy.
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Size past which files are rotated, with [LogRotation::Size].
+    #[serde(default = "default_max_size_bytes")]
+    pub max_size_bytes: u64,
+    /// Number of files retained, including the current one, at least 1.
+    /// Older files are deleted on rotation. Unlimited if unset.
+    #[serde(default)]
+    pub max_files: Option<usize>,
+    /// [EnvFilter] directive of this file. Follows the runtime log filter if
+    /// unset. A set level is fixed: admin `/log-level` changes don't apply
+    /// to it.
+    ///
+    /// [EnvFilter]: tracing_subscriber::EnvFilter
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+fn default_max_size_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+/// Limits on the volume of log events, e.g. under a hot error loop.
+///
+/// Events are rate limited per callsite, and debug and info events can
+/// additionally be sampled. Warnings and errors are never sampled, and
+/// neither are events of debug requests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogSampling {
+    /// Maximum events logged per callsite within `interval_ms`. Further
+    /// events are dropped, and their count logged as a `suppressed events`
+    /// line with the callsite's next event. Unlimited if unset, else at
+    /// least 1.
+    pub max_events: Option<u32>,
+    /// Rate limiting interval, in milliseconds, at least 1.
+    pub interval_ms: u64,
+    /// Fraction of debug (and trace) events logged, between 0 and 1.
+    pub debug_ratio: f64,
+    /// Fraction of info events logged, between 0 and 1.
+    pub info_ratio: f64,
+}
+
+impl Default for LogSampling {
+    fn default() -> Self {
+        Self {
+            max_events: None,
+            interval_ms: 1000,
+            debug_ratio: 1.0,
+            info_ratio: 1.0,
+        }
+    }
+}
+
+impl LogSampling {
+    /// Convert `interval_ms` to [Duration].
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+/// Per-request log level elevation: requests carrying a valid debug header
+/// are logged at `level` within their span tree, and propagate a signed
+/// header to outbound client calls to `forward_hosts`. Disabled unless
+/// `tokens` or a `signing_key` are set.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DebugRequest {
+    /// Request header name.
+    pub header: String,
+    /// Log level within debug requests, e.g. `debug` or `trace`.
+    pub level: String,
+    /// Allowlisted header values.
+    #[serde(serialize_with = "serialize_redacted_list")]
+    pub tokens: Vec<String>,
+    /// Key for HMAC-SHA256 signed, expiring header values, formatted as
+    /// `<expiry unix seconds>.<nonce>.<base64url signature>`, signing
+    /// `debug-request.<expiry>.<nonce>`.
+    #[serde(serialize_with = "serialize_redacted_option")]
+    pub signing_key: Option<String>,
+    /// Maximum time until the expiry of signed header values, in seconds.
+    /// Values expiring later are rejected.
+    pub max_ttl_secs: u64,
+    /// Hosts of outbound calls the header is forwarded to, e.g.
+    /// `api.internal`, or `.svc.cluster.local` for any subdomain. Only
+    /// signed values are forwarded: requests elevated by a token forward a
+    /// freshly signed value instead, if a `signing_key` is set.
+    pub forward_hosts: Vec<String>,
+}
+
+impl DebugRequest {
+    /// Convert `max_ttl_secs` to [Duration].
+    pub fn max_ttl(&self) -> Duration {
+        Duration::from_secs(self.max_ttl_secs)
+    }
+}
+
+impl Default for DebugRequest {
+    fn default() -> Self {
+        Self {
+            header: "x-debug-request".to_string(),
+            level: "debug".to_string(),
+            tokens: vec![],
+            signing_key: None,
+            max_ttl_secs: 3_600,
+            forward_hosts: vec![],
+        }
+    }
+}
+
+impl std::fmt::Debug for DebugRequest {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("DebugRequest")
+            .field("header", &self.header)
+            .field("level", &self.level)
+            .field("tokens", &vec![REDACTED; self.tokens.len()])
+            .field("signing_key", &self.signing_key.as_ref().map(|_| REDACTED))
+            .field("max_ttl_secs", &self.max_ttl_secs)
+            .field("forward_hosts", &self.forward_hosts)
+            .finish()
+    }
+}
+
+/// Redaction rules applied to logged payloads.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Redaction {
+    /// Header names treated as sensitive on server requests/responses and
+    /// outbound client requests/responses.
+    pub headers: Vec<String>,
+    /// [JSON pointers] to redact within JSON bodies, e.g. `/user/password`.
+    ///
+    /// [JSON pointers]: <https://datatracker.ietf.org/doc/html/rfc6901>
+    pub json_pointers: Vec<String>,
+    /// Case-insensitive field names to redact from JSON bodies and query
+    /// strings. `*` matches any run of characters, e.g. `*token*`.
+    pub fields: Vec<String>,
+    /// Regular expressions whose matches are redacted from free text and
+    /// string values.
+    pub patterns: Vec<String>,
+    /// Sensitive header names logged in plain text in the `local` and `dev`
+    /// environments. Credential headers (`authorization`,
+    /// `proxy-authorization`, `cookie` and `set-cookie`) are redacted
+    /// regardless.
+    pub reveal_headers: Vec<String>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            headers: vec![
+                "authorization".to_string(),
+                "proxy-authorization".to_string(),
+                "cookie".to_string(),
+                "set-cookie".to_string(),
+            ],
+            json_pointers: vec![],
+            fields: vec![
+                "*password*".to_string(),
+                "*secret*".to_string(),
+                "*token*".to_string(),
+                "api_key".to_string(),
+                "apikey".to_string(),
+            ],
+            patterns: vec![],
+            reveal_headers: vec![],
+        }
+    }
+}
+
+/// Trace sampler types, for root spans and spans continuing an incoming
+/// trace context. Spans with a local parent follow its sampling decision.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerType {
+    /// Sample every trace, unless the incoming trace context isn't sampled.
+    #[default]
+    AlwaysOn,
+    /// Sample no traces, unless the incoming trace context is sampled.
+    AlwaysOff,
+    /// Sample a ratio of traces, based on their trace id, whatever the
+    /// decision of the incoming trace context.
+    Ratio,
+    /// Follow the decision of the incoming trace context, and sample a ratio
+    /// of traces without one, as with [SamplerType::Ratio].
+    ParentBasedRatio,
+}
+
+/// Trace sampling settings.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Sampling {
+    /// [SamplerType] for spans not matched by a rule.
+    pub sampler: SamplerType,
+    /// Ratio of traces (0.0 to 1.0) sampled by ratio-based samplers.
+    pub ratio: f64,
+    /// Request paths whose spans are always dropped, e.g. `/healthcheck`.
+    pub drop_paths: Vec<String>,
+    /// Always export spans that end with an error status, even when not
+    /// sampled. Unsampled spans are then recorded, at about the cost of
+    /// sampled ones, and error spans may be exported without their parents.
+    pub keep_errors: bool,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            sampler: SamplerType::default(),
+            ratio: 1.0,
+            drop_paths: vec!["/healthcheck".to_string(), "/metrics".to_string()],
+            keep_errors: true,
+        }
+    }
+}
+
+/// Trace context propagation formats, named as in `OTEL_PROPAGATORS`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Propagator {
+    /// [W3C trace context](https://www.w3.org/TR/trace-context/).
+    TraceContext,
+    /// [W3C baggage](https://www.w3.org/TR/baggage/).
+    Baggage,
+    /// [B3](https://github.com/openzipkin/b3-propagation) single `b3` header.
+    B3,
+    /// [B3](https://github.com/openzipkin/b3-propagation) multiple `X-B3-*`
+    /// headers.
+    B3Multi,
+    /// Jaeger `uber-trace-id` header.
+    Jaeger,
+}
+
+fn default_propagators() -> Vec<Propagator> {
+    vec![Propagator::TraceContext, Propagator::Baggage]
+}
+
+/// Incoming [W3C baggage](https://www.w3.org/TR/baggage/) entries logged as
+/// `baggage.<key>` fields of request spans. Callers set baggage freely, so
+/// only the listed `keys` are logged, with values truncated.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggedBaggage {
+    /// Baggage keys logged, none by default.
+    pub keys: Vec<String>,
+    /// Maximum length of logged values, in bytes.
+    pub max_value_length: usize,
+}
+
+impl Default for LoggedBaggage {
+    fn default() -> Self {
+        Self {
+            keys: vec![],
+            max_value_length: 128,
+        }
+    }
+}
+
+/// Trace exporter modes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExporterMode {
+    /// Export traces (and optionally logs and metrics) to an OTLP collector.
+    #[default]
+    Otlp,
+    /// Write finished spans to stdout, e.g. for local development without
+    /// a collector.
+    Stdout,
+    /// Don't export traces. Spans and trace context are still created and
+    /// propagated.
+    None,
+}
+
+fn default_otlp_endpoint() -> Uri {
+    Uri::from_static("http://localhost:4317")
+}
+
+/// OTLP exporter transports, named as in `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// Protobuf over gRPC, via [tonic].
+    #[default]
+    #[serde(rename = "grpc")]
+    Grpc,
+    /// Protobuf over HTTP, posted to the `/v1/<signal>` paths of the
+    /// endpoint.
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+}
+
+/// OTLP export compression.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpCompression {
+    /// Gzip compression (gRPC only).
+    Gzip,
 }
 
 /// [Opentelemetry] settings.
 ///
 /// [Opentelemetry]: https://opentelemetry.io/
 #[serde_as]
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Otel {
-    /// Exporter [Uri] for OTEL protocol.
-    #[serde(with = "http_serde::uri")]
+    /// Trace [ExporterMode], OTLP by default. Logs and metrics are only
+    /// exported over OTLP in `otlp` mode.
+    #[serde(default)]
+    pub exporter: ExporterMode,
+    /// Exporter [Uri] for OTEL protocol (`http://localhost:4317` if unset).
+    #[serde(with = "http_serde::uri", default = "default_otlp_endpoint")]
     pub exporter_otlp_endpoint: Uri,
+    /// Exporter transport, gRPC by default.
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+    /// Headers (gRPC metadata) sent with each export, e.g. vendor API keys.
+    /// Values are treated as secrets, and never logged.
+    #[serde(default, serialize_with = "serialize_redacted_values")]
+    pub headers: HashMap<String, String>,
+    /// Export timeout in milliseconds (10s if unset).
+    pub timeout_ms: Option<u64>,
+    /// Optional compression of exported payloads.
+    pub compression: Option<OtlpCompression>,
+    /// Extra resource attributes, added to (or overriding) the service name,
+    /// version, deployment environment, host and instance id.
+    #[serde(default)]
+    pub resource_attributes: HashMap<String, String>,
+    /// Export log events to the OTLP endpoint, alongside traces.
+    #[serde(default)]
+    pub export_logs: bool,
+    /// Push metrics to the OTLP endpoint, alongside the Prometheus scrape
+    /// endpoint.
+    #[serde(default)]
+    pub export_metrics: bool,
+    /// Interval in milliseconds between OTLP metrics pushes (60s if unset).
+    pub metrics_export_interval_ms: Option<u64>,
+    /// Trace [Sampling] settings.
+    #[serde(default)]
+    pub sampling: Sampling,
+    /// Formats used to extract and inject trace context and baggage, in
+    /// order (W3C trace context and baggage by default).
+    #[serde(default = "default_propagators")]
+    pub propagators: Vec<Propagator>,
+    /// Incoming baggage entries logged with requests.
+    #[serde(default)]
+    pub logged_baggage: LoggedBaggage,
+}
+
+impl Otel {
+    /// Whether logs are exported over OTLP.
+    pub fn exports_logs(&self) -> bool {
+        self.exporter == ExporterMode::Otlp && self.export_logs
+    }
+
+    /// Whether metrics are pushed over OTLP.
+    pub fn exports_metrics(&self) -> bool {
+        self.exporter == ExporterMode::Otlp && self.export_metrics
+    }
+
+    /// Convert `timeout_ms` to [Duration].
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.unwrap_or(10_000))
+    }
+
+    /// Convert `metrics_export_interval_ms` to [Duration].
+    pub fn metrics_export_interval(&self) -> Duration {
+        Duration::from_millis(self.metrics_export_interval_ms.unwrap_or(60_000))
+    }
 }
 
 impl std::fmt::Debug for Otel {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         fmt.debug_struct("Otel")
+            .field("exporter", &self.exporter)
             .field("exporter_otlp_endpoint", &self.exporter_otlp_endpoint)
+            .field("protocol", &self.protocol)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .keys()
+                    .map(|name| (name, REDACTED))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .field("timeout_ms", &self.timeout_ms)
+            .field("compression", &self.compression)
+            .field("resource_attributes", &self.resource_attributes)
+            .field("export_logs", &self.export_logs)
+            .field("export_metrics", &self.export_metrics)
+            .field(
+                "metrics_export_interval_ms",
+                &self.metrics_export_interval_ms,
+            )
+            .field("sampling", &self.sampling)
+            .field("propagators", &self.propagators)
+            .field("logged_baggage", &self.logged_baggage)
             .finish()
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// Admin server settings, for the server on the metrics port.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Admin {
+    /// Authentication required for all admin endpoints, including
+    /// `/metrics`. Unauthenticated if unset, with mutating endpoints
+    /// (`PUT /log-level`) disabled.
+    pub auth: Option<AdminAuth>,
+}
+
+/// Admin server authentication schemes, e.g.
+/// `{ type = "bearer", token = "..." }`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AdminAuth {
+    /// HTTP basic authentication.
+    Basic {
+        /// Expected username.
+        username: String,
+        /// Expected password.
+        #[serde(serialize_with = "serialize_redacted")]
+        password: String,
+    },
+    /// Bearer token authentication.
+    Bearer {
+        /// Expected token.
+        #[serde(serialize_with = "serialize_redacted")]
+        token: String,
+    },
+}
+
+impl std::fmt::Debug for AdminAuth {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Basic { username, .. } => fmt
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &REDACTED)
+                .finish(),
+            Self::Bearer { .. } => fmt
+                .debug_struct("Bearer")
+                .field("token", &REDACTED)
+                .finish(),
+        }
+    }
+}
+
+fn serialize_redacted<S: Serializer>(_secret: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+fn serialize_redacted_list<S: Serializer>(
+    secrets: &[String],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(secrets.iter().map(|_| REDACTED))
+}
+
+fn serialize_redacted_option<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_some(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_redacted_values<S: Serializer>(
+    map: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.keys().map(|name| (name, REDACTED)))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 /// Application settings.
 pub struct Settings {
+    #[serde(default)]
+    admin: Admin,
+    #[serde(default)]
+    logging: Logging,
+    #[serde(default)]
+    metrics: Metrics,
     monitoring: Monitoring,
     server: Server,
     otel: Otel,
 }
 
 impl Settings {
+    /// Admin server settings getter.
+    pub fn admin(&self) -> &Admin {
+        &self.admin
+    }
+
     /// Environment settings getter.
     pub fn environment(&self) -> AppEnvironment {
         self.server().environment
     }
 
+    /// Logging settings getter.
+    pub fn logging(&self) -> &Logging {
+        &self.logging
+    }
+
+    /// Metrics settings getter.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     /// Monitoring settings getter.
     pub fn monitoring(&self) -> &Monitoring {
         &self.monitoring
@@ -115,7 +863,7 @@ impl Settings {
 }
 
 /// Http-client retry options.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct HttpClientRetryOptions {
     /// Retry count.
     pub count: u8,
@@ -136,7 +884,7 @@ impl Default for HttpClientRetryOptions {
 }
 
 /// Settings for Http clients.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct HttpClient {
     /// Optional timeout for idle sockets being kept-alive.
     /// Using `None` to disable timeout.
@@ -242,4 +990,83 @@ mod tests {
         assert_eq!(settings.http_client.retry_options.count, 1);
         assert_eq!(settings.http_client.timeout_ms, 10_000);
     }
+
+    #[test]
+    fn test_otel_debug_redacts_headers() {
+        let settings = Otel {
+            exporter: ExporterMode::Otlp,
+            exporter_otlp_endpoint: "http://localhost:4317".parse().unwrap(),
+            protocol: OtlpProtocol::HttpProtobuf,
+            headers: HashMap::from([("x-api-key".to_string(), "secret".to_string())]),
+            timeout_ms: None,
+            compression: None,
+            resource_attributes: HashMap::new(),
+            export_logs: false,
+            export_metrics: false,
+            metrics_export_interval_ms: None,
+            sampling: Sampling::default(),
+            propagators: default_propagators(),
+            logged_baggage: LoggedBaggage::default(),
+        };
+
+        let debug = format!("{settings:?}");
+        assert!(debug.contains("x-api-key"));
+        assert!(!debug.contains("secret"));
+        assert_eq!(settings.timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_admin_auth_serialize_redacts_secrets() {
+        let admin: Admin = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [auth]
+                type = "basic"
+                username = "admin"
+                password = "secret"
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let json = serde_json::to_value(&admin).unwrap();
+        assert_eq!(json["auth"]["type"], "basic");
+        assert_eq!(json["auth"]["username"], "admin");
+        assert_eq!(json["auth"]["password"], REDACTED);
+        assert!(!format!("{admin:?}").contains("secret"));
+    }
+
+    #[test]
+    fn test_metrics_buckets_matchers() {
+        let metrics: Metrics = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                quantiles = [0.5, 0.99]
+                idle_timeout_secs = 300
+
+                [[buckets]]
+                prefix = "db_"
+                buckets = [0.01, 0.1, 1]
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(
+            metrics.buckets,
+            vec![MetricBuckets {
+                matcher: MetricMatcher::Prefix("db_".to_string()),
+                buckets: vec![0.01, 0.1, 1.0],
+            }]
+        );
+        assert_eq!(metrics.quantiles, vec![0.5, 0.99]);
+        assert_eq!(metrics.idle_timeout(), Some(Duration::from_secs(300)));
+        assert!(metrics.global_labels.is_empty());
+    }
 }
@@ -1,5 +1,10 @@
-/// Random value generator for sampling data.
+{% if bench %}/// Random value generator for sampling data.
 #[cfg(feature = "test_utils")]
 mod rvg;
 #[cfg(feature = "test_utils")]
 pub use rvg::*;
+{% endif %}pub mod recorder;
+pub mod subscriber;
+
+pub use recorder::LocalRecorder;
+pub use subscriber::{SpanNode, TestSubscriber};
@@ -1,63 +1,903 @@
 //! Opentelemetry tracing extensions and setup.
 
-use crate::settings::Otel;
+use crate::{
+    propagation::composite_propagator,
+    settings::{
+        AppEnvironment, ExporterMode, Otel, OtlpCompression, OtlpProtocol, SamplerType, Sampling,
+    },
+};
 use anyhow::{anyhow, Result};
 use const_format::formatcp;
-use http::Uri;
-use opentelemetry::{global, KeyValue};
-use opentelemetry_otlp::{TonicExporterBuilder, WithExportConfig};
+use futures::future::{self, BoxFuture};
+use once_cell::sync::Lazy;
+use opentelemetry::{
+    global,
+    trace::{
+        Link, SamplingDecision, SamplingResult, SpanContext, SpanKind, Status, TraceContextExt,
+        TraceFlags, TraceId, TracerProvider as _,
+    },
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{
+    Compression, HttpExporterBuilder, LogExporterBuilder, MetricsExporterBuilder, Protocol,
+    SpanExporterBuilder, TonicExporterBuilder, WithExportConfig,
+};
 use opentelemetry_sdk::{
-    self, propagation::TraceContextPropagator, runtime, trace::Tracer, Resource,
+    self,
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    logs::LoggerProvider,
+    runtime,
+    trace::{
+        BatchSpanProcessor, Sampler, ShouldSample, Span, SpanProcessor, Tracer, TracerProvider,
+    },
+    Resource,
 };
 use opentelemetry_semantic_conventions as otel_semcov;
-use tonic::{metadata::MetadataMap, transport::ClientTlsConfig};
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+use sysinfo::{System, SystemExt};
+use tonic::{
+    metadata::{MetadataKey, MetadataMap, MetadataValue},
+    transport::ClientTlsConfig,
+};
+use ulid::Ulid;
 
-//const PKG_NAME: &str = env!("CARGO_PKG_NAME");
-const PKG_NAME: &str = "application";
+const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = formatcp!("v{}", env!("CARGO_PKG_VERSION"));
 const LANG: &str = "rust";
 
-/// Initialize Opentelemetry tracing via the [OTLP protocol].
+/// Paths appended to the endpoint for each signal, over HTTP.
+pub(crate) const TRACES_PATH: &str = "/v1/traces";
+pub(crate) const LOGS_PATH: &str = "/v1/logs";
+pub(crate) const METRICS_PATH: &str = "/v1/metrics";
+
+/// Identifier of this process, shared by the resources of all signals.
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| Ulid::new().to_string());
+
+/// Span attributes holding the request path, checked against
+/// [Sampling::drop_paths].
+const PATH_ATTRIBUTES: [&str; 3] = ["http.route", "url.path", "http.target"];
+
+/// Initialize Opentelemetry tracing, exporting spans as set by the
+/// [ExporterMode]: via the [OTLP protocol], to stdout, or not at all.
+///
+/// Installs the configured propagators as the global text map propagator,
+/// used to extract incoming and inject outgoing trace context and baggage.
+///
+/// Spans are sampled by a [RuleSampler] built from the [Sampling] settings,
+/// with unsampled error spans kept by an [ErrorSpanProcessor] if enabled.
+///
+/// [OTLP protocol]: <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/otlp.md>
+pub fn init_tracer(settings: &Otel, environment: AppEnvironment) -> Result<Tracer> {
+    let builder = tracer_provider_builder(settings, environment);
+
+    let provider = match settings.exporter {
+        ExporterMode::Otlp => {
+            let exporter = SpanExporterBuilder::from(exporter(settings, TRACES_PATH)?)
+                .build_span_exporter()
+                .map_err(|e| anyhow!("failed to intialize tracer: {:#?}", e))?;
+            let processor = BatchSpanProcessor::builder(exporter, runtime::Tokio).build();
+            builder.with_span_processor(ErrorSpanProcessor::new(processor))
+        }
+        ExporterMode::Stdout => {
+            let processor = EagerSpanProcessor::new(opentelemetry_stdout::SpanExporter::default());
+            builder.with_span_processor(ErrorSpanProcessor::new(processor))
+        }
+        ExporterMode::None => builder,
+    }
+    .build();
+
+    Ok(install_tracer_provider(settings, provider))
+}
+
+/// Initialize Opentelemetry tracing exporting spans, as they end, to the
+/// given `exporter`, e.g. an [InMemoryExporter] in tests.
+///
+/// Otherwise set up as by [init_tracer], regardless of the [ExporterMode].
+pub fn init_tracer_with_exporter<E>(
+    settings: &Otel,
+    environment: AppEnvironment,
+    exporter: E,
+) -> Tracer
+where
+    E: SpanExporter + 'static,
+{
+    let processor = EagerSpanProcessor::new(exporter);
+    let provider = tracer_provider_builder(settings, environment)
+        .with_span_processor(ErrorSpanProcessor::new(processor))
+        .build();
+
+    install_tracer_provider(settings, provider)
+}
+
+fn tracer_provider_builder(
+    settings: &Otel,
+    environment: AppEnvironment,
+) -> opentelemetry_sdk::trace::Builder {
+    TracerProvider::builder().with_config(
+        opentelemetry_sdk::trace::config()
+            .with_sampler(RuleSampler::new(&settings.sampling))
+            .with_resource(resource(settings, environment)),
+    )
+}
+
+/// Install the configured propagators and `provider` globally, returning a
+/// [Tracer] from it.
+fn install_tracer_provider(settings: &Otel, provider: TracerProvider) -> Tracer {
+    global::set_text_map_propagator(composite_propagator(&settings.propagators));
+
+    let tracer = provider
+        .tracer_builder("opentelemetry-otlp")
+        .with_version(env!("CARGO_PKG_VERSION"))
+        .build();
+    let _ = global::set_tracer_provider(provider);
+
+    tracer
+}
+
+/// [SpanProcessor] exporting each span as it ends, so that spans are visible
+/// as soon as they end (unlike with a [BatchSpanProcessor]), for exporters
+/// writing spans as the export starts, like the stdout and
+/// [InMemoryExporter]s.
+///
+/// The exporter is only locked to start exports. Within a Tokio runtime, the
+/// rest of an export is spawned, rather than blocking the runtime's threads.
+#[derive(Debug)]
+struct EagerSpanProcessor<E> {
+    exporter: Mutex<E>,
+}
+
+impl<E: SpanExporter> EagerSpanProcessor<E> {
+    fn new(exporter: E) -> Self {
+        Self {
+            exporter: Mutex::new(exporter),
+        }
+    }
+}
+
+impl<E: SpanExporter> SpanProcessor for EagerSpanProcessor<E> {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        if !span.span_context.is_sampled() {
+            return;
+        }
+
+        let export = self.exporter.lock().export(vec![span]);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(err) = export.await {
+                        global::handle_error(err);
+                    }
+                });
+            }
+            Err(_) => {
+                if let Err(err) = futures::executor::block_on(export) {
+                    global::handle_error(err);
+                }
+            }
+        }
+    }
+
+    fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+        self.exporter.lock().shutdown();
+        Ok(())
+    }
+}
+
+/// [SpanExporter] keeping exported spans in memory, so that tests can assert
+/// on the spans emitted.
+///
+/// Clones share the same spans.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl InMemoryExporter {
+    /// Create a new, empty [InMemoryExporter].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spans exported so far.
+    pub fn finished_spans(&self) -> Vec<SpanData> {
+        self.spans.lock().clone()
+    }
+
+    /// Clear exported spans.
+    pub fn reset(&self) {
+        self.spans.lock().clear();
+    }
+}
+
+impl SpanExporter for InMemoryExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.spans.lock().extend(batch);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+/// Sampler dropping spans for configured request paths, and deferring to the
+/// configured [SamplerType] otherwise. Spans with a local parent follow its
+/// decision, so that children of dropped spans are dropped too, rather than
+/// exported as orphans. Spans with a remote parent follow its decision too,
+/// but with [SamplerType::Ratio].
+///
+/// When keeping errors, spans the underlying sampler would drop are still
+/// recorded (but not sampled), so that [ErrorSpanProcessor] can export them
+/// if they end with an error. Recording them costs as much as sampling them,
+/// short of exporting, so it's best disabled under a low sampling ratio on
+/// hot paths.
+#[derive(Clone, Debug)]
+pub struct RuleSampler {
+    sampler: Sampler,
+    drop_paths: Vec<String>,
+    keep_errors: bool,
+}
+
+impl RuleSampler {
+    /// Create a new [RuleSampler] from [Sampling] settings.
+    pub fn new(settings: &Sampling) -> Self {
+        let sampler = match settings.sampler {
+            SamplerType::AlwaysOn => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+            SamplerType::AlwaysOff => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+            SamplerType::Ratio => Sampler::TraceIdRatioBased(settings.ratio),
+            SamplerType::ParentBasedRatio => {
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(settings.ratio)))
+            }
+        };
+
+        Self {
+            sampler,
+            drop_paths: settings.drop_paths.clone(),
+            keep_errors: settings.keep_errors,
+        }
+    }
+
+    fn is_dropped_path(&self, attributes: &[KeyValue]) -> bool {
+        attributes.iter().any(|kv| {
+            PATH_ATTRIBUTES.contains(&kv.key.as_str())
+                && self
+                    .drop_paths
+                    .iter()
+                    .any(|path| *path == kv.value.as_str())
+        })
+    }
+}
+
+impl ShouldSample for RuleSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        if self.is_dropped_path(attributes) {
+            return SamplingResult {
+                decision: SamplingDecision::Drop,
+                attributes: Vec::new(),
+                trace_state: parent_context
+                    .map(|cx| cx.span().span_context().trace_state().clone())
+                    .unwrap_or_default(),
+            };
+        }
+
+        let local_parent = parent_context
+            .map(|cx| cx.span().span_context().clone())
+            .filter(|parent| parent.is_valid() && !parent.is_remote());
+
+        let mut result = match local_parent {
+            Some(parent) if !parent.is_sampled() => SamplingResult {
+                decision: SamplingDecision::Drop,
+                attributes: Vec::new(),
+                trace_state: parent.trace_state().clone(),
+            },
+            _ => self.sampler.should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            ),
+        };
+
+        if self.keep_errors && result.decision == SamplingDecision::Drop {
+            result.decision = SamplingDecision::RecordOnly;
+        }
+
+        result
+    }
+}
+
+/// [SpanProcessor] passing sampled spans, and recorded-only spans that ended
+/// with an error status, on to an inner processor.
+#[derive(Debug)]
+pub struct ErrorSpanProcessor<P> {
+    inner: P,
+}
+
+impl<P: SpanProcessor> ErrorSpanProcessor<P> {
+    /// Wrap an inner [SpanProcessor], e.g. a [BatchSpanProcessor].
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for ErrorSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        if !span.span_context.is_sampled() {
+            if !matches!(span.status, Status::Error { .. }) {
+                return;
+            }
+
+            let context = &span.span_context;
+            span.span_context = SpanContext::new(
+                context.trace_id(),
+                context.span_id(),
+                context.trace_flags() | TraceFlags::SAMPLED,
+                context.is_remote(),
+                context.trace_state().clone(),
+            );
+        }
+
+        self.inner.on_end(span)
+    }
+
+    fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Initialize an Opentelemetry [LoggerProvider] exporting log records via the
+/// [OTLP protocol], to the same endpoint and with the same resource
+/// attributes as traces.
+///
+/// Events are bridged to the provider by
+/// [OtelLogLayer](crate::tracing_layers::otel_log_layer::OtelLogLayer).
 ///
 /// [OTLP protocol]: <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/otlp.md>
-pub fn init_tracer(settings: &Otel) -> Result<Tracer> {
-    global::set_text_map_propagator(TraceContextPropagator::new());
+pub fn init_logger_provider(
+    settings: &Otel,
+    environment: AppEnvironment,
+) -> Result<LoggerProvider> {
+    let provider = opentelemetry_otlp::new_pipeline()
+        .logging()
+        .with_exporter(exporter(settings, LOGS_PATH)?)
+        .with_log_config(
+            opentelemetry_sdk::logs::config().with_resource(resource(settings, environment)),
+        )
+        .install_batch(runtime::Tokio)
+        .map_err(|e| anyhow!("failed to intialize logger provider: {:#?}", e))?;
 
-    let resource = Resource::new(vec![
+    Ok(provider)
+}
+
+/// Resource attributes shared by exported traces, logs and metrics: the
+/// service name and version, deployment environment, host name and instance
+/// id, plus any configured `resource_attributes`.
+pub(crate) fn resource(settings: &Otel, environment: AppEnvironment) -> Resource {
+    let mut attributes = vec![
         KeyValue::new(otel_semcov::resource::SERVICE_NAME, PKG_NAME),
         KeyValue::new(otel_semcov::resource::SERVICE_VERSION, VERSION),
         KeyValue::new(otel_semcov::resource::TELEMETRY_SDK_LANGUAGE, LANG),
-    ]);
+        KeyValue::new(
+            otel_semcov::resource::DEPLOYMENT_ENVIRONMENT,
+            environment.to_string(),
+        ),
+        KeyValue::new(
+            otel_semcov::resource::SERVICE_INSTANCE_ID,
+            INSTANCE_ID.as_str(),
+        ),
+    ];
+
+    if let Some(host_name) = System::new().host_name() {
+        attributes.push(KeyValue::new(otel_semcov::resource::HOST_NAME, host_name));
+    }
+
+    let resource = Resource::new(attributes);
+    if settings.resource_attributes.is_empty() {
+        resource
+    } else {
+        resource.merge(&Resource::new(
+            settings
+                .resource_attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+        ))
+    }
+}
+
+/// OTLP exporter over the configured [OtlpProtocol].
+#[derive(Debug)]
+pub(crate) enum Exporter {
+    /// gRPC transport.
+    Tonic(Box<TonicExporterBuilder>),
+    /// HTTP/protobuf transport.
+    Http(HttpExporterBuilder),
+}
+
+impl From<Exporter> for SpanExporterBuilder {
+    fn from(exporter: Exporter) -> Self {
+        match exporter {
+            Exporter::Tonic(builder) => (*builder).into(),
+            Exporter::Http(builder) => builder.into(),
+        }
+    }
+}
+
+impl From<Exporter> for LogExporterBuilder {
+    fn from(exporter: Exporter) -> Self {
+        match exporter {
+            Exporter::Tonic(builder) => (*builder).into(),
+            Exporter::Http(builder) => builder.into(),
+        }
+    }
+}
 
+impl From<Exporter> for MetricsExporterBuilder {
+    fn from(exporter: Exporter) -> Self {
+        match exporter {
+            Exporter::Tonic(builder) => (*builder).into(),
+            Exporter::Http(builder) => builder.into(),
+        }
+    }
+}
+
+/// Build an [Exporter] from [Otel] settings. Over HTTP, `signal_path` (e.g.
+/// [TRACES_PATH]) is appended to the endpoint.
+pub(crate) fn exporter(settings: &Otel, signal_path: &str) -> Result<Exporter> {
     let endpoint = &settings.exporter_otlp_endpoint;
 
-    let map = MetadataMap::with_capacity(2);
+    match settings.protocol {
+        OtlpProtocol::Grpc => {
+            let mut map = MetadataMap::with_capacity(settings.headers.len());
+            for (name, value) in &settings.headers {
+                let key = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+                    .map_err(|_| anyhow!("invalid otel header name: {name}"))?;
+                let value = MetadataValue::try_from(value.as_str())
+                    .map_err(|_| anyhow!("invalid value for otel header: {name}"))?;
+                map.insert(key, value);
+            }
 
-    let trace = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(exporter(map, endpoint)?)
-        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(resource))
-        .install_batch(runtime::Tokio)
-        .map_err(|e| anyhow!("failed to intialize tracer: {:#?}", e))?;
+            // Over grpc transport
+            let mut exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.to_string())
+                .with_timeout(settings.timeout())
+                .with_metadata(map);
+
+            if let Some(OtlpCompression::Gzip) = settings.compression {
+                exporter = exporter.with_compression(Compression::Gzip);
+            }
 
-    Ok(trace)
+            match endpoint.scheme_str() {
+                Some("https") => {
+                    let host = endpoint
+                        .host()
+                        .ok_or_else(|| anyhow!("failed to parse host"))?;
+
+                    Ok(Exporter::Tonic(Box::new(exporter.with_tls_config(
+                        ClientTlsConfig::new().domain_name(host.to_string()),
+                    ))))
+                }
+                _ => Ok(Exporter::Tonic(Box::new(exporter))),
+            }
+        }
+        OtlpProtocol::HttpProtobuf => {
+            if settings.compression.is_some() {
+                return Err(anyhow!("otel compression is only supported over grpc"));
+            }
+
+            let endpoint = format!(
+                "{}{signal_path}",
+                endpoint.to_string().trim_end_matches('/')
+            );
+            let headers = settings
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect::<HashMap<_, _>>();
+
+            Ok(Exporter::Http(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_protocol(Protocol::HttpBinary)
+                    .with_endpoint(endpoint)
+                    .with_timeout(settings.timeout())
+                    .with_headers(headers),
+            ))
+        }
+    }
 }
 
-fn exporter(map: MetadataMap, endpoint: &Uri) -> Result<TonicExporterBuilder> {
-    // Over grpc transport
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_endpoint(endpoint.to_string())
-        .with_metadata(map);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        settings::LoggedBaggage,
+        tracing_layers::{otel_log_layer::OtelLogLayer, storage_layer::StorageLayer},
+    };
+    use opentelemetry::trace::{Span as _, SpanId, TraceState, Tracer as _};
+    use opentelemetry_proto::tonic::{
+        collector::logs::v1::{
+            logs_service_server::{LogsService, LogsServiceServer},
+            ExportLogsServiceRequest, ExportLogsServiceResponse,
+        },
+        common::v1::any_value::Value,
+    };
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Server, Request, Response, Status};
+    use tracing_subscriber::prelude::*;
 
-    match endpoint.scheme_str() {
-        Some("https") => {
-            let host = endpoint
-                .host()
-                .ok_or_else(|| anyhow!("failed to parse host"))?;
+    /// Fake OTLP collector keeping received log export requests, and their
+    /// `x-api-key` metadata.
+    #[derive(Clone, Default)]
+    struct FakeCollector {
+        requests: Arc<Mutex<Vec<ExportLogsServiceRequest>>>,
+        api_keys: Arc<Mutex<Vec<String>>>,
+    }
 
-            Ok(exporter.with_tls_config(ClientTlsConfig::new().domain_name(host.to_string())))
+    #[tonic::async_trait]
+    impl LogsService for FakeCollector {
+        async fn export(
+            &self,
+            request: Request<ExportLogsServiceRequest>,
+        ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+            if let Some(key) = request.metadata().get("x-api-key") {
+                self.api_keys
+                    .lock()
+                    .unwrap()
+                    .push(key.to_str().unwrap().to_string());
+            }
+            self.requests.lock().unwrap().push(request.into_inner());
+            Ok(Response::new(ExportLogsServiceResponse {
+                partial_success: None,
+            }))
         }
-        _ => Ok(exporter),
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_logs_with_trace_context() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = FakeCollector::default();
+        tokio::spawn(
+            Server::builder()
+                .add_service(LogsServiceServer::new(collector.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let settings = Otel {
+            exporter: ExporterMode::Otlp,
+            exporter_otlp_endpoint: format!("http://{addr}").parse().unwrap(),
+            protocol: OtlpProtocol::Grpc,
+            headers: HashMap::from([("X-Api-Key".to_string(), "secret".to_string())]),
+            timeout_ms: None,
+            compression: None,
+            resource_attributes: HashMap::from([("team".to_string(), "platform".to_string())]),
+            export_logs: true,
+            export_metrics: false,
+            metrics_export_interval_ms: None,
+            sampling: Sampling::default(),
+            propagators: vec![],
+            logged_baggage: LoggedBaggage::default(),
+        };
+        let logger_provider = init_logger_provider(&settings, AppEnvironment::Dev).unwrap();
+        let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let tracer = tracer_provider.tracer("test");
+
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default())
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(OtelLogLayer::new(&logger_provider));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "abc");
+            let _guard = span.enter();
+            tracing::info!(subject = "test", count = 2, "hello collector");
+        });
+
+        for result in logger_provider.force_flush() {
+            result.unwrap();
+        }
+
+        assert_eq!(*collector.api_keys.lock().unwrap(), vec!["secret"]);
+
+        let requests = collector.requests.lock().unwrap();
+        let resource_logs = &requests[0].resource_logs[0];
+        let resource_attribute = |key: &str| {
+            resource_logs
+                .resource
+                .as_ref()
+                .unwrap()
+                .attributes
+                .iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.as_ref()?.value.clone())
+        };
+        assert_eq!(
+            resource_attribute(otel_semcov::resource::SERVICE_NAME),
+            Some(Value::StringValue(PKG_NAME.to_string()))
+        );
+        assert_eq!(
+            resource_attribute(otel_semcov::resource::DEPLOYMENT_ENVIRONMENT),
+            Some(Value::StringValue("dev".to_string()))
+        );
+        assert_eq!(
+            resource_attribute(otel_semcov::resource::SERVICE_INSTANCE_ID),
+            Some(Value::StringValue(INSTANCE_ID.to_string()))
+        );
+        assert_eq!(
+            resource_attribute("team"),
+            Some(Value::StringValue("platform".to_string()))
+        );
+
+        let record = &resource_logs.scope_logs[0].log_records[0];
+        assert_eq!(record.severity_text, "INFO");
+        assert_eq!(
+            record.body.as_ref().and_then(|body| body.value.clone()),
+            Some(Value::StringValue("hello collector".to_string()))
+        );
+        assert_eq!(record.trace_id.len(), 16);
+        assert_eq!(record.span_id.len(), 8);
+
+        let attribute = |key: &str| {
+            record
+                .attributes
+                .iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.as_ref()?.value.clone())
+        };
+        assert_eq!(attribute("count"), Some(Value::IntValue(2)));
+        assert_eq!(
+            attribute("request_id"),
+            Some(Value::StringValue("abc".to_string()))
+        );
+    }
+
+    #[test]
+    fn exports_spans_in_memory() {
+        let settings = Otel {
+            exporter: ExporterMode::None,
+            exporter_otlp_endpoint: "http://localhost:4317".parse().unwrap(),
+            protocol: OtlpProtocol::Grpc,
+            headers: HashMap::new(),
+            timeout_ms: None,
+            compression: None,
+            resource_attributes: HashMap::new(),
+            export_logs: false,
+            export_metrics: false,
+            metrics_export_interval_ms: None,
+            sampling: Sampling::default(),
+            propagators: vec![],
+            logged_baggage: LoggedBaggage::default(),
+        };
+        let exporter = InMemoryExporter::new();
+        let tracer = init_tracer_with_exporter(&settings, AppEnvironment::Local, exporter.clone());
+
+        tracer.in_span("kept", |_cx| {});
+        tracer
+            .span_builder("dropped")
+            .with_attributes([KeyValue::new("http.route", "/healthcheck")])
+            .start(&tracer)
+            .end();
+
+        let names = exporter
+            .finished_spans()
+            .into_iter()
+            .map(|span| span.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["kept"]);
+
+        exporter.reset();
+        assert!(exporter.finished_spans().is_empty());
+    }
+
+    #[tokio::test]
+    async fn exports_without_blocking_on_exporter() {
+        /// Exporter whose exports never complete.
+        #[derive(Debug, Default)]
+        struct PendingExporter(Arc<Mutex<usize>>);
+
+        impl SpanExporter for PendingExporter {
+            fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+                *self.0.lock().unwrap() += batch.len();
+                Box::pin(future::pending())
+            }
+        }
+
+        let exporter = PendingExporter::default();
+        let exported = exporter.0.clone();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(EagerSpanProcessor::new(exporter))
+            .build();
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("first", |_cx| {});
+        tracer.in_span("second", |_cx| {});
+        assert_eq!(*exported.lock().unwrap(), 2);
+    }
+
+    /// Span processor collecting ended spans.
+    #[derive(Clone, Debug, Default)]
+    struct CollectingProcessor {
+        spans: Arc<Mutex<Vec<SpanData>>>,
+    }
+
+    impl SpanProcessor for CollectingProcessor {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.spans.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    fn sampled_span_names(sampling: Sampling, spans: &[(&'static str, &str, bool)]) -> Vec<String> {
+        let collector = CollectingProcessor::default();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(ErrorSpanProcessor::new(collector.clone()))
+            .with_config(
+                opentelemetry_sdk::trace::config().with_sampler(RuleSampler::new(&sampling)),
+            )
+            .build();
+        let tracer = provider.tracer("test");
+
+        for (name, path, error) in spans {
+            let mut span = tracer
+                .span_builder(*name)
+                .with_attributes([KeyValue::new("url.path", path.to_string())])
+                .start(&tracer);
+            if *error {
+                span.set_status(opentelemetry::trace::Status::error("failed"));
+            }
+            span.end();
+        }
+
+        let spans = collector.spans.lock().unwrap();
+        assert!(spans.iter().all(|span| span.span_context.is_sampled()));
+        spans.iter().map(|span| span.name.to_string()).collect()
+    }
+
+    #[test]
+    fn drops_configured_paths() {
+        let names = sampled_span_names(
+            Sampling::default(),
+            &[
+                ("healthcheck", "/healthcheck", false),
+                ("metrics", "/metrics", true),
+                ("ping", "/ping", false),
+            ],
+        );
+        assert_eq!(names, vec!["ping"]);
+    }
+
+    #[test]
+    fn keeps_unsampled_error_spans() {
+        let sampling = Sampling {
+            sampler: SamplerType::AlwaysOff,
+            ..Sampling::default()
+        };
+        let names = sampled_span_names(
+            sampling.clone(),
+            &[("ok", "/ping", false), ("failed", "/ping", true)],
+        );
+        assert_eq!(names, vec!["failed"]);
+
+        let names = sampled_span_names(
+            Sampling {
+                keep_errors: false,
+                ..sampling
+            },
+            &[("ok", "/ping", false), ("failed", "/ping", true)],
+        );
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn drops_children_of_dropped_spans() {
+        let collector = CollectingProcessor::default();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(ErrorSpanProcessor::new(collector.clone()))
+            .with_config(
+                opentelemetry_sdk::trace::config()
+                    .with_sampler(RuleSampler::new(&Sampling::default())),
+            )
+            .build();
+        let tracer = provider.tracer("test");
+
+        let parent = tracer
+            .span_builder("healthcheck")
+            .with_attributes([KeyValue::new("url.path", "/healthcheck")])
+            .start(&tracer);
+        let cx = Context::current_with_span(parent);
+        tracer.start_with_context("child", &cx).end();
+        drop(cx);
+
+        assert!(collector.spans.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn samples_by_ratio() {
+        let sampling = |sampler, ratio| Sampling {
+            sampler,
+            ratio,
+            keep_errors: false,
+            ..Sampling::default()
+        };
+
+        let names = sampled_span_names(
+            sampling(SamplerType::Ratio, 0.0),
+            &[("ping", "/ping", false)],
+        );
+        assert!(names.is_empty());
+
+        let names = sampled_span_names(
+            sampling(SamplerType::ParentBasedRatio, 1.0),
+            &[("ping", "/ping", false)],
+        );
+        assert_eq!(names, vec!["ping"]);
+    }
+
+    #[test]
+    fn follows_remote_parents_but_by_ratio() {
+        let sampled_children = |sampler| {
+            let collector = CollectingProcessor::default();
+            let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+                .with_span_processor(ErrorSpanProcessor::new(collector.clone()))
+                .with_config(
+                    opentelemetry_sdk::trace::config().with_sampler(RuleSampler::new(&Sampling {
+                        sampler,
+                        ratio: 0.0,
+                        keep_errors: false,
+                        ..Sampling::default()
+                    })),
+                )
+                .build();
+            let tracer = provider.tracer("test");
+
+            let remote_parent = SpanContext::new(
+                TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+                SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::default(),
+            );
+            let cx = Context::new().with_remote_span_context(remote_parent);
+            tracer.start_with_context("child", &cx).end();
+
+            let spans = collector.spans.lock().unwrap();
+            spans.len()
+        };
+
+        assert_eq!(sampled_children(SamplerType::ParentBasedRatio), 1);
+        assert_eq!(sampled_children(SamplerType::Ratio), 0);
     }
 }
@@ -2,29 +2,46 @@
 //!
 //! Inspired by [influxdata's (Influx DB's) version].
 //!
+//! Can alternatively output the same fields as newline-delimited JSON, via
+//! [LogFmtLayer::with_format], and bound the log volume via
+//! [LogFmtLayer::with_sampler].
+//!
 //! [Logfmt]: <https://brandur.org/logfmt>
 //! [Layer]: tracing_subscriber::Layer
 //! [influxdata's (Influx DB's) version]: <https://github.com/influxdata/influxdb_iox/tree/main/logfmt>
 
-use crate::tracing_layers::storage_layer::Storage;
+use crate::{
+    settings::LogFormat,
+    tracing_layers::{
+        log_sampler::{LogSampler, Sample},
+        storage_layer::{Storage, StorageReader},
+    },
+};
 use parking_lot::RwLock;
+use serde_json::{Map, Value};
 use std::{
     borrow::Cow,
     fmt,
     io::{self, Write},
-    time::SystemTime,
+    sync::Arc,
+    time::{Instant, SystemTime},
 };
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tracing::{
     field::{Field, Visit},
     metadata::LevelFilter,
     span::{Attributes, Id},
-    Event, Level, Subscriber,
+    Event, Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{format::FmtSpan, MakeWriter},
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    Layer,
 };
-use tracing_subscriber::{fmt::MakeWriter, layer::Context, registry::LookupSpan, Layer};
 
 /// Fields to persist from [Storage](Storage) for `new_span` logs via context.
-const SPAN_FIELDS: [&str; 13] = [
+pub const SPAN_FIELDS: [&str; 13] = [
     "category",
     "follows_from",
     "follows_from.trace_id",
@@ -42,7 +59,7 @@ const SPAN_FIELDS: [&str; 13] = [
 
 /// Fields to skip from [Storage](Storage) spans for `on_event` logs via
 /// context.
-const ON_EVENT_SKIP_FIELDS: [&str; 6] = [
+pub const ON_EVENT_SKIP_FIELDS: [&str; 6] = [
     "authorization",
     "category",
     "error",
@@ -53,7 +70,7 @@ const ON_EVENT_SKIP_FIELDS: [&str; 6] = [
 
 /// Fields to persist from [Storage](Storage) for `on_close` span logs via
 /// context.
-const ON_CLOSE_FIELDS: [&str; 12] = [
+pub const ON_CLOSE_FIELDS: [&str; 12] = [
     "category",
     "follows_from",
     "follows_from.trace_id",
@@ -71,6 +88,64 @@ const ON_CLOSE_FIELDS: [&str; 12] = [
 #[cfg(feature = "ansi-logs")]
 const GRAY: u8 = 245;
 
+/// Fields of [Storage](Storage) spans written to log lines, defaulting to
+/// [SPAN_FIELDS], [ON_EVENT_SKIP_FIELDS] and [ON_CLOSE_FIELDS].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanFields {
+    /// Fields written on `new_span` lines of info and higher spans. Lines of
+    /// debug and trace spans have all fields.
+    pub new_span: Vec<String>,
+    /// Fields skipped on event lines, which have all others.
+    pub on_event_skip: Vec<String>,
+    /// Fields written on `close_span` lines.
+    pub on_close: Vec<String>,
+}
+
+impl Default for SpanFields {
+    fn default() -> Self {
+        let to_vec = |fields: &[&str]| fields.iter().map(|f| f.to_string()).collect();
+        Self {
+            new_span: to_vec(&SPAN_FIELDS),
+            on_event_skip: to_vec(&ON_EVENT_SKIP_FIELDS),
+            on_close: to_vec(&ON_CLOSE_FIELDS),
+        }
+    }
+}
+
+impl SpanFields {
+    /// Write `fields` on every line of spans carrying them, e.g. `tenant_id`
+    /// or `user_id`.
+    pub fn promote<I, F>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        for field in fields.into_iter().map(Into::into) {
+            self.on_event_skip.retain(|skipped| *skipped != field);
+            for list in [&mut self.new_span, &mut self.on_close] {
+                if !list.contains(&field) {
+                    list.push(field.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// Skip `fields` on event lines.
+    pub fn skip<I, F>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        for field in fields.into_iter().map(Into::into) {
+            if !self.on_event_skip.contains(&field) {
+                self.on_event_skip.push(field);
+            }
+        }
+        self
+    }
+}
+
 /// Logging layer for formatting and outputting event-driven logs.
 #[derive(Debug)]
 pub struct LogFmtLayer<Wr, W = fn() -> io::Stdout>
@@ -79,7 +154,11 @@ where
     W: for<'writer> MakeWriter<'writer>,
 {
     writer: W,
-    printer: RwLock<FieldPrinter<Wr>>,
+    printer: Arc<RwLock<FieldPrinter<Wr>>>,
+    sampler: Arc<LogSampler>,
+    span_fields: SpanFields,
+    span_events: FmtSpan,
+    storage_reader: StorageReader,
 }
 
 impl<Wr, W> LogFmtLayer<Wr, W>
@@ -115,7 +194,15 @@ where
         let make_writer = writer.make_writer();
         Self {
             writer,
-            printer: RwLock::new(FieldPrinter::new(make_writer, true)),
+            printer: Arc::new(RwLock::new(FieldPrinter::new(
+                make_writer,
+                true,
+                LogFormat::default(),
+            ))),
+            sampler: Arc::new(LogSampler::default()),
+            span_fields: SpanFields::default(),
+            span_events: FmtSpan::NEW | FmtSpan::CLOSE,
+            storage_reader: StorageReader::default(),
         }
     }
 
@@ -123,50 +210,149 @@ where
     ///
     /// Note: this API mimics that of other fmt layers in tracing-subscriber crate.
     pub fn with_target(self, display_target: bool) -> Self {
-        let make_writer = self.writer.make_writer();
+        self.printer.write().display_target = display_target;
         Self {
             writer: self.writer,
-            printer: RwLock::new(FieldPrinter::new(make_writer, display_target)),
+            printer: self.printer,
+            sampler: self.sampler,
+            span_fields: self.span_fields,
+            span_events: self.span_events,
+            storage_reader: self.storage_reader,
+        }
+    }
+
+    /// Set the output [LogFormat] (logfmt by default).
+    ///
+    /// [LogFormat::Json] writes each log line as a JSON object, with event
+    /// fields kept as their recorded types and stored span values parsed as
+    /// numbers or booleans where possible. Span values named as an event
+    /// field are prefixed with `span.`.
+    pub fn with_format(self, format: LogFormat) -> Self {
+        self.printer.write().format = format;
+        Self {
+            writer: self.writer,
+            printer: self.printer,
+            sampler: self.sampler,
+            span_fields: self.span_fields,
+            span_events: self.span_events,
+            storage_reader: self.storage_reader,
+        }
+    }
+
+    /// Rate limit and sample events with a [LogSampler] (keeping every event
+    /// by default).
+    ///
+    /// Events suppressed by rate limiting are reported as a
+    /// `suppressed events` line, with their count, before the callsite's next
+    /// logged event, or once their interval ends if the callsite logs no
+    /// further events, from a thread spawned when the layer is registered.
+    pub fn with_sampler(self, sampler: LogSampler) -> Self {
+        Self {
+            sampler: Arc::new(sampler),
+            ..self
+        }
+    }
+
+    /// Set which span lifecycle events are logged ([FmtSpan::NEW] and
+    /// [FmtSpan::CLOSE] by default), as with
+    /// [tracing_subscriber::fmt::Layer::with_span_events].
+    ///
+    /// Close lines include the span's `time.busy` (entered) and `time.idle`
+    /// times.
+    pub fn with_span_events(self, span_events: FmtSpan) -> Self {
+        Self {
+            span_events,
+            ..self
+        }
+    }
+
+    /// Set which [SpanFields] are written to log lines.
+    pub fn with_span_fields(self, span_fields: SpanFields) -> Self {
+        Self {
+            span_fields,
+            ..self
+        }
+    }
+
+    /// Write the fields of a [StorageReader] stored on the current span on
+    /// event lines, even if this layer's filter disables the span, e.g. the
+    /// `request_id` of a request span logged at `info` on lines of a sink
+    /// logging warnings only.
+    pub fn with_storage_reader(self, storage_reader: StorageReader) -> Self {
+        Self {
+            storage_reader,
+            ..self
         }
     }
 }
 
 impl<S, Wr, W> Layer<S> for LogFmtLayer<Wr, W>
 where
-    Wr: Write + 'static,
+    Wr: Write + Send + Sync + 'static,
     W: for<'writer> MakeWriter<'writer> + 'static,
     S: Subscriber + for<'span> LookupSpan<'span>,
 {
+    fn on_layer(&mut self, _subscriber: &mut S) {
+        let Some(interval) = self.sampler.flush_interval() else {
+            return;
+        };
+
+        // Report floods that ended without a later event of their callsite.
+        // Stops once the layer is dropped.
+        let sampler = Arc::downgrade(&self.sampler);
+        let printer = Arc::downgrade(&self.printer);
+        let _ = std::thread::Builder::new()
+            .name("log-sampler-flush".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let (Some(sampler), Some(printer)) = (sampler.upgrade(), printer.upgrade()) else {
+                    return;
+                };
+                for (metadata, suppressed) in sampler.flush() {
+                    printer.write().write_suppressed(metadata, suppressed);
+                }
+            });
+    }
+
     fn max_level_hint(&self) -> Option<LevelFilter> {
         None
     }
 
     fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+
+        if self.span_events.clone() & FmtSpan::CLOSE == FmtSpan::CLOSE {
+            let mut extensions = span.extensions_mut();
+            // Shared with other log layers, e.g. of log files.
+            if extensions.get_mut::<Timings>().is_none() {
+                extensions.insert(Timings::new());
+            }
+        }
+
+        if self.span_events.clone() & FmtSpan::NEW != FmtSpan::NEW {
+            return;
+        }
+
         let mut p = self.printer.write();
 
-        let metadata = ctx.metadata(id).expect("Span missing metadata");
+        let metadata = span.metadata();
         p.write_level(metadata.level());
         p.write_span_name(metadata.name());
         p.write_span_id(id);
         p.write_span_event("new_span");
         p.write_timestamp();
 
-        let span = ctx.span(id).expect("Span not found");
         let extensions = span.extensions();
         if let Some(visitor) = extensions.get::<Storage<'_>>() {
             for (key, value) in visitor.values() {
                 match *metadata.level() {
-                    Level::TRACE | Level::DEBUG => p.write_kv(
-                        decorate_field_name(translate_field_name(key)),
-                        value.to_string(),
-                    ),
+                    Level::TRACE | Level::DEBUG => {
+                        p.write_span_kv(translate_field_name(key), value)
+                    }
 
                     _ => {
-                        if SPAN_FIELDS.contains(key) {
-                            p.write_kv(
-                                decorate_field_name(translate_field_name(key)),
-                                value.to_string(),
-                            )
+                        if contains(&self.span_fields.new_span, key) {
+                            p.write_span_kv(translate_field_name(key), value)
                         }
                     }
                 }
@@ -175,55 +361,125 @@ where
         p.write_newline();
     }
 
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+
+        if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+            timings.enter();
+        }
+
+        if self.span_events.clone() & FmtSpan::ENTER == FmtSpan::ENTER {
+            self.write_span_line(&span, "enter_span", None);
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+
+        if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+            timings.exit();
+        }
+
+        if self.span_events.clone() & FmtSpan::EXIT == FmtSpan::EXIT {
+            self.write_span_line(&span, "exit_span", None);
+        }
+    }
+
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let suppressed = match self.sampler.sample(event.metadata()) {
+            Sample::Drop => return,
+            Sample::Keep { suppressed } => suppressed,
+        };
+
         let mut p = self.printer.write();
 
+        if suppressed > 0 {
+            p.write_suppressed(event.metadata(), suppressed);
+        }
+
         p.write_level(event.metadata().level());
         event.record(&mut *p);
 
         //record source information
-        p.write_source_info(event);
+        p.write_source_info(event.metadata());
         p.write_timestamp();
 
-        ctx.lookup_current().map(|current_span| {
+        let visible_span = ctx.lookup_current();
+
+        // Fields of the current span this layer's filter disables.
+        let mut unfiltered_fields = ctx
+            .current_span()
+            .id()
+            .filter(|id| visible_span.as_ref().map_or(true, |span| span.id() != **id))
+            .map(|id| self.storage_reader.fields(id))
+            .unwrap_or_default();
+
+        visible_span.map(|current_span| {
             p.write_span_id(&current_span.id());
             let extensions = current_span.extensions();
             extensions.get::<Storage<'_>>().map(|visitor| {
+                unfiltered_fields.retain(|(key, _)| !visitor.values().contains_key(key.as_str()));
                 for (key, value) in visitor.values() {
-                    if !ON_EVENT_SKIP_FIELDS.contains(key) {
-                        p.write_kv(
-                            decorate_field_name(translate_field_name(key)),
-                            value.to_string(),
-                        )
+                    if !contains(&self.span_fields.on_event_skip, key) {
+                        p.write_span_kv(translate_field_name(key), value)
                     }
                 }
             })
         });
 
+        for (key, value) in unfiltered_fields {
+            if !contains(&self.span_fields.on_event_skip, &key) {
+                p.write_span_kv(translate_field_name(&key), &value)
+            }
+        }
+
         p.write_newline();
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
-        let mut p = self.printer.write();
+        if self.span_events.clone() & FmtSpan::CLOSE != FmtSpan::CLOSE {
+            return;
+        }
 
-        let metadata = ctx.metadata(&id).expect("Span missing metadata");
         let span = ctx.span(&id).expect("Span not found");
+        let timings = span.extensions().get::<Timings>().map(Timings::close);
+        self.write_span_line(&span, "close_span", timings);
+    }
+}
 
-        p.write_level(metadata.level());
-        p.write_span_name(metadata.name());
+impl<Wr, W> LogFmtLayer<Wr, W>
+where
+    Wr: Write,
+    W: for<'writer> MakeWriter<'writer>,
+{
+    /// Write a span lifecycle line, with the span's [SpanFields::on_close]
+    /// fields, and its busy and idle times (in nanoseconds) on close.
+    fn write_span_line<S>(
+        &self,
+        span: &SpanRef<'_, S>,
+        span_event: &str,
+        timings: Option<(u64, u64)>,
+    ) where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let mut p = self.printer.write();
+
+        p.write_level(span.metadata().level());
+        p.write_span_name(span.name());
         p.write_span_id(&span.id());
-        p.write_span_event("close_span");
+        p.write_span_event(span_event);
         p.write_timestamp();
 
-        let mut extensions = span.extensions_mut();
+        if let Some((busy, idle)) = timings {
+            p.write_timing("time.busy", busy);
+            p.write_timing("time.idle", idle);
+        }
 
-        if let Some(visitor) = extensions.get_mut::<Storage<'_>>() {
+        let extensions = span.extensions();
+        if let Some(visitor) = extensions.get::<Storage<'_>>() {
             for (key, value) in visitor.values() {
-                if ON_CLOSE_FIELDS.contains(key) {
-                    p.write_kv(
-                        decorate_field_name(translate_field_name(key)),
-                        value.to_string(),
-                    )
+                if contains(&self.span_fields.on_close, key) {
+                    p.write_span_kv(translate_field_name(key), value)
                 }
             }
         }
@@ -232,19 +488,87 @@ where
     }
 }
 
+/// Busy (entered) and idle times of a span, as in [tracing_subscriber::fmt].
+#[derive(Debug)]
+struct Timings {
+    busy: u64,
+    idle: u64,
+    last: Instant,
+    entered: u64,
+}
+
+impl Timings {
+    fn new() -> Self {
+        Self {
+            busy: 0,
+            idle: 0,
+            last: Instant::now(),
+            entered: 0,
+        }
+    }
+
+    fn enter(&mut self) {
+        if self.entered == 0 {
+            let now = Instant::now();
+            self.idle += (now - self.last).as_nanos() as u64;
+            self.last = now;
+        }
+        self.entered += 1;
+    }
+
+    fn exit(&mut self) {
+        self.entered = self.entered.saturating_sub(1);
+        if self.entered == 0 {
+            let now = Instant::now();
+            self.busy += (now - self.last).as_nanos() as u64;
+            self.last = now;
+        }
+    }
+
+    /// Busy and idle times, in nanoseconds, counting the time since last
+    /// exited as idle.
+    fn close(&self) -> (u64, u64) {
+        (self.busy, self.idle + self.last.elapsed().as_nanos() as u64)
+    }
+}
+
 /// This is responsible for actually printing log information to
 /// the layer's writer.
 #[derive(Debug)]
 struct FieldPrinter<Wr: io::Write> {
     writer: Wr,
     display_target: bool,
+    format: LogFormat,
+    /// Fields buffered for the current line when writing [LogFormat::Json].
+    json: Map<String, Value>,
 }
 
 impl<W: Write> FieldPrinter<W> {
-    fn new(writer: W, display_target: bool) -> Self {
+    fn new(writer: W, display_target: bool, format: LogFormat) -> Self {
         Self {
             writer,
             display_target,
+            format,
+            json: Map::new(),
+        }
+    }
+
+    /// Write a single field, as ` key=value` for logfmt or buffered into the
+    /// current JSON object.
+    fn write_field(&mut self, key: &str, logfmt_value: &dyn fmt::Display, json_value: Value) {
+        match self.format {
+            LogFormat::Logfmt => {
+                write!(
+                    self.writer,
+                    " {}={}",
+                    decorate_field_name(key),
+                    logfmt_value
+                )
+                .ok();
+            }
+            LogFormat::Json => {
+                self.json.insert(key.to_string(), json_value);
+            }
         }
     }
 
@@ -256,8 +580,13 @@ impl<W: Write> FieldPrinter<W> {
             Level::INFO => "info",
             Level::WARN => "warn",
             Level::ERROR => "error",
+        };
+
+        if self.format == LogFormat::Json {
+            self.json
+                .insert("level".to_string(), Value::from(level_str));
+            return;
         }
-        .to_uppercase();
 
         let level_name = match *level {
             Level::TRACE => ansi_term::Color::Purple,
@@ -267,7 +596,7 @@ impl<W: Write> FieldPrinter<W> {
             Level::ERROR => ansi_term::Color::Red,
         }
         .bold()
-        .paint(level_str);
+        .paint(level_str.to_uppercase());
 
         write!(
             self.writer,
@@ -288,6 +617,12 @@ impl<W: Write> FieldPrinter<W> {
             Level::ERROR => "error",
         };
 
+        if self.format == LogFormat::Json {
+            self.json
+                .insert("level".to_string(), Value::from(level_str));
+            return;
+        }
+
         write!(
             self.writer,
             r#"{}={}"#,
@@ -298,171 +633,169 @@ impl<W: Write> FieldPrinter<W> {
     }
 
     fn write_span_name(&mut self, value: &str) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name("span_name"),
-            quote_and_escape(value)
-        )
-        .ok();
+        self.write_field("span_name", &quote_and_escape(value), Value::from(value));
     }
 
-    fn write_source_info(&mut self, event: &Event<'_>) {
+    fn write_source_info(&mut self, metadata: &Metadata<'_>) {
         if !self.display_target {
             return;
         }
 
-        let metadata = event.metadata();
-
         if metadata.target() != "log" {
-            write!(
-                self.writer,
-                " {}=\"{}\"",
-                decorate_field_name("target"),
-                quote_and_escape(metadata.target())
-            )
-            .ok();
+            self.write_field(
+                "target",
+                &format_args!("\"{}\"", quote_and_escape(metadata.target())),
+                Value::from(metadata.target()),
+            );
         }
 
         if let Some(module_path) = metadata.module_path() {
             if metadata.target() != module_path {
-                write!(
-                    self.writer,
-                    " {}=\"{}\"",
-                    decorate_field_name("module_path"),
-                    module_path
-                )
-                .ok();
+                self.write_field(
+                    "module_path",
+                    &format_args!("\"{module_path}\""),
+                    Value::from(module_path),
+                );
             }
         }
         if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
-            write!(
-                self.writer,
-                " {}=\"{}:{}\"",
-                decorate_field_name("location"),
-                file,
-                line
-            )
-            .ok();
+            let location = format!("{file}:{line}");
+            self.write_field(
+                "location",
+                &format_args!("\"{location}\""),
+                Value::from(location.as_str()),
+            );
         }
     }
 
+    /// Write a summary line of `count` events suppressed at the callsite of
+    /// `event`.
+    fn write_suppressed(&mut self, metadata: &Metadata<'_>, count: u64) {
+        let msg = format!("suppressed {count} events");
+        self.write_level(metadata.level());
+        self.write_field("msg", &quote_and_escape(&msg), Value::from(msg.as_str()));
+        self.write_field("suppressed", &count, Value::from(count));
+        self.write_source_info(metadata);
+        self.write_timestamp();
+        self.write_newline();
+    }
+
+    /// Write a duration of `nanos`, displayed as by [tracing_subscriber::fmt]
+    /// (e.g. `1.23ms`), or as a number of nanoseconds in JSON.
+    fn write_timing(&mut self, key: &str, nanos: u64) {
+        self.write_field(key, &TimingDisplay(nanos), Value::from(nanos));
+    }
+
     fn write_span_id(&mut self, id: &Id) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name("span"),
-            id.into_u64()
-        )
-        .ok();
+        self.write_field("span", &id.into_u64(), Value::from(id.into_u64()));
     }
 
     fn write_span_event(&mut self, hook: &str) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name("span_event"),
-            hook
-        )
-        .ok();
+        self.write_field("span_event", &hook, Value::from(hook));
     }
 
     fn write_timestamp(&mut self) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name("timestamp"),
-            to_rfc3339(&SystemTime::now())
-        )
-        .ok();
+        let timestamp = to_rfc3339(&SystemTime::now());
+        self.write_field("timestamp", &timestamp, Value::from(timestamp.as_str()));
     }
 
-    fn write_kv(&mut self, key: String, value: String) {
-        write!(self.writer, " {}={}", key, quote_and_escape(value.as_str())).ok();
+    fn write_kv(&mut self, key: &str, value: &str) {
+        self.write_field(key, &quote_and_escape(value), typed_value(value));
+    }
+
+    /// Write a stored span field. In JSON, a field already written for the
+    /// line (e.g. by the event) is kept, and the span's is prefixed with
+    /// `span.` instead.
+    fn write_span_kv(&mut self, key: &str, value: &str) {
+        if self.format == LogFormat::Json && self.json.contains_key(key) {
+            self.write_kv(&format!("span.{key}"), value);
+        } else {
+            self.write_kv(key, value);
+        }
     }
 
     fn write_newline(&mut self) {
-        writeln!(self.writer).ok();
+        match self.format {
+            LogFormat::Logfmt => {
+                writeln!(self.writer).ok();
+            }
+            LogFormat::Json => {
+                let line = std::mem::take(&mut self.json);
+                serde_json::to_writer(&mut self.writer, &line).ok();
+                writeln!(self.writer).ok();
+            }
+        }
     }
 }
 
 impl<W: io::Write> Visit for FieldPrinter<W> {
     /// Visit a signed 64-bit integer value.
     fn record_i64(&mut self, field: &Field, value: i64) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name(translate_field_name(field.name())),
-            value
-        )
-        .ok();
+        self.write_field(
+            translate_field_name(field.name()),
+            &value,
+            Value::from(value),
+        );
     }
 
     /// Visit an unsigned 64-bit integer value.
     fn record_u64(&mut self, field: &Field, value: u64) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name(translate_field_name(field.name())),
-            value
-        )
-        .ok();
+        self.write_field(
+            translate_field_name(field.name()),
+            &value,
+            Value::from(value),
+        );
+    }
+
+    /// Visit a double-precision floating point value.
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.write_field(translate_field_name(field.name()), &value, json_f64(value));
     }
 
     /// Visit a boolean value.
     fn record_bool(&mut self, field: &Field, value: bool) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name(translate_field_name(field.name())),
-            value
-        )
-        .ok();
+        self.write_field(
+            translate_field_name(field.name()),
+            &value,
+            Value::from(value),
+        );
     }
 
     /// Visit a string value.
     fn record_str(&mut self, field: &Field, value: &str) {
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name(translate_field_name(field.name())),
-            quote_and_escape(value)
-        )
-        .ok();
+        self.write_field(
+            translate_field_name(field.name()),
+            &quote_and_escape(value),
+            Value::from(value),
+        );
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
         // Note this appears to be invoked via `debug!` and `info! macros
         let formatted_value = format!("{value:?}");
-        write!(
-            self.writer,
-            " {}={}",
-            decorate_field_name(translate_field_name(field.name())),
-            quote_and_escape(&formatted_value)
-        )
-        .ok();
+        self.write_field(
+            translate_field_name(field.name()),
+            &quote_and_escape(&formatted_value),
+            Value::from(formatted_value.as_str()),
+        );
     }
 
     fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
         let field_name = translate_field_name(field.name());
 
         let debug_formatted = format!("{value:?}");
-        write!(
-            self.writer,
-            " {}={:?}",
-            decorate_field_name(field_name),
-            quote_and_escape(&debug_formatted)
-        )
-        .ok();
+        self.write_field(
+            field_name,
+            &format_args!("{:?}", quote_and_escape(&debug_formatted)),
+            Value::from(debug_formatted.as_str()),
+        );
 
         let display_formatted = format!("{value}");
-        write!(
-            self.writer,
-            " {}.display={}",
-            decorate_field_name(field_name),
-            quote_and_escape(&display_formatted)
-        )
-        .ok();
+        self.write_field(
+            &format!("{field_name}.display"),
+            &quote_and_escape(&display_formatted),
+            Value::from(display_formatted.as_str()),
+        );
     }
 }
 
@@ -488,6 +821,31 @@ impl fmt::Display for Type {
     }
 }
 
+/// Whether `fields` contains `key`.
+fn contains(fields: &[String], key: &str) -> bool {
+    fields.iter().any(|field| field == key)
+}
+
+/// Displays nanoseconds with a unit and up to 3 significant digits.
+struct TimingDisplay(u64);
+
+impl fmt::Display for TimingDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut t = self.0 as f64;
+        for unit in ["ns", "µs", "ms", "s"] {
+            if t < 10.0 {
+                return write!(f, "{t:.2}{unit}");
+            } else if t < 100.0 {
+                return write!(f, "{t:.1}{unit}");
+            } else if t < 1000.0 {
+                return write!(f, "{t:.0}{unit}");
+            }
+            t /= 1000.0;
+        }
+        write!(f, "{:.0}s", t * 1000.0)
+    }
+}
+
 /// Translate the field name from tracing into the logfmt style.
 fn translate_field_name(name: &str) -> &str {
     let name = slice_field_name(name);
@@ -526,6 +884,28 @@ fn to_rfc3339(st: &SystemTime) -> String {
         .unwrap_or_default()
 }
 
+/// Parse stored (stringified) span values back into JSON numbers or booleans
+/// where possible.
+fn typed_value(value: &str) -> Value {
+    if let Ok(v) = value.parse::<i64>() {
+        Value::from(v)
+    } else if let Ok(v) = value.parse::<u64>() {
+        Value::from(v)
+    } else if let Some(v) = value.parse::<f64>().ok().filter(|v| v.is_finite()) {
+        json_f64(v)
+    } else if let Ok(v) = value.parse::<bool>() {
+        Value::from(v)
+    } else {
+        Value::from(value)
+    }
+}
+
+/// JSON number of a float, or a string if it's not finite (e.g. `NaN`).
+fn json_f64(value: f64) -> Value {
+    serde_json::Number::from_f64(value)
+        .map_or_else(|| Value::from(value.to_string()), Value::Number)
+}
+
 /// Return true if the string value already starts/ends with quotes and is
 /// already properly escaped (all spaces escaped).
 fn needs_quotes_and_escaping(value: &str) -> bool {
@@ -583,6 +963,273 @@ fn slice_field_name(name: &str) -> &str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::tracing_layers::storage_layer::StorageLayer;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TestWriter {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    fn log_with_format(format: LogFormat) -> Vec<String> {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default())
+            .with(
+                LogFmtLayer::new(move || make_writer.clone())
+                    .with_target(false)
+                    .with_format(format),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "request",
+                request_id = "abc",
+                latency_ms = 3,
+                count = 1,
+                ratio = 0.5
+            );
+            let _guard = span.enter();
+            tracing::info!(
+                subject = "test",
+                count = 2,
+                ok = true,
+                ratio = 0.25,
+                "hello world"
+            );
+        });
+
+        writer.lines()
+    }
+
+    #[test]
+    fn logfmt_output() {
+        let lines = log_with_format(LogFormat::Logfmt);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1]
+            .starts_with("level=info msg=\"hello world\" subject=test count=2 ok=true ratio=0.25"));
+        assert!(lines[1].contains(" request_id=abc"));
+    }
+
+    #[test]
+    fn json_output() {
+        let lines = log_with_format(LogFormat::Json);
+        assert_eq!(lines.len(), 3);
+
+        let new_span: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(new_span["span_event"], "new_span");
+        assert_eq!(new_span["span_name"], "request");
+
+        let event: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(event["level"], "info");
+        assert_eq!(event["msg"], "hello world");
+        assert_eq!(event["subject"], "test");
+        assert_eq!(event["count"], 2);
+        assert_eq!(event["ok"], true);
+        assert_eq!(event["request_id"], "abc");
+        assert_eq!(event["latency_ms"], 3);
+        assert!(event["span"].is_u64());
+        // Event fields are kept over same-named span fields.
+        assert_eq!(event["ratio"], 0.25);
+        assert_eq!(event["span.count"], 1);
+        assert_eq!(event["span.ratio"], 0.5);
+
+        let close_span: Value = serde_json::from_str(&lines[2]).unwrap();
+        assert_eq!(close_span["span_event"], "close_span");
+    }
+
+    #[test]
+    fn promoted_span_fields_output() {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default().keep_event_fields(["tenant_id"]))
+            .with(
+                LogFmtLayer::new(move || make_writer.clone())
+                    .with_target(false)
+                    .with_span_fields(
+                        SpanFields::default()
+                            .promote(["user_id", "tenant_id"])
+                            .skip(["request_id"]),
+                    ),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "abc", user_id = 7);
+            let _guard = span.enter();
+            tracing::info!(tenant_id = "acme", attempt = 1, "resolved tenant");
+            tracing::info!("hello");
+        });
+
+        let lines = writer.lines();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains(" user_id=7"));
+        assert!(!lines[2].contains("request_id"));
+        // Kept from the previous event, without its other fields or message.
+        assert!(lines[2].starts_with("level=info msg=hello "));
+        assert!(lines[2].contains(" tenant_id=acme"));
+        assert!(!lines[2].contains("attempt"));
+        assert!(!lines[2].contains("resolved tenant"));
+        assert!(lines[3].contains(" user_id=7"));
+        assert!(lines[3].contains(" tenant_id=acme"));
+        assert!(!lines[3].contains("resolved tenant"));
+    }
+
+    fn log_span_events(span_events: FmtSpan) -> Vec<String> {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(StorageLayer::default())
+            .with(
+                LogFmtLayer::new(move || make_writer.clone())
+                    .with_target(false)
+                    .with_span_events(span_events),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "abc");
+            span.in_scope(|| std::thread::sleep(std::time::Duration::from_millis(5)));
+            span.in_scope(|| tracing::info!("hello"));
+        });
+
+        writer.lines()
+    }
+
+    #[test]
+    fn span_events_output() {
+        let lines = log_span_events(FmtSpan::NONE);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("level=info msg=hello"));
+
+        let lines = log_span_events(FmtSpan::ACTIVE);
+        let events: Vec<_> = lines
+            .iter()
+            .filter_map(|line| line.split(" span_event=").nth(1))
+            .map(|rest| rest.split(' ').next().unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            ["enter_span", "exit_span", "enter_span", "exit_span"]
+        );
+
+        let lines = log_span_events(FmtSpan::CLOSE);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains(" span_event=close_span"));
+        assert!(lines[1].contains(" request_id=abc"));
+        let busy = lines[1].split(" time.busy=").nth(1).unwrap();
+        assert!(busy.starts_with(|c: char| c.is_ascii_digit()));
+        assert!(busy.split(' ').next().unwrap().ends_with("ms"));
+        assert!(lines[1].contains(" time.idle="));
+    }
+
+    #[test]
+    fn reads_fields_of_filtered_spans() {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let storage_layer = StorageLayer::default();
+        let storage_reader = storage_layer.reader(["request_id"]);
+        let subscriber = tracing_subscriber::registry().with(storage_layer).with(
+            LogFmtLayer::new(move || make_writer.clone())
+                .with_target(false)
+                .with_storage_reader(storage_reader)
+                .with_filter(LevelFilter::WARN),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "abc", latency_ms = 3);
+            let _guard = span.enter();
+            tracing::warn!("slow");
+        });
+
+        let lines = writer.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("level=warn msg=slow"));
+        assert!(lines[0].contains(" request_id=abc"));
+        assert!(!lines[0].contains("latency_ms"));
+    }
+
+    #[test]
+    fn timing_display() {
+        assert_eq!(TimingDisplay(5).to_string(), "5.00ns");
+        assert_eq!(TimingDisplay(12_345).to_string(), "12.3µs");
+        assert_eq!(TimingDisplay(123_456_789).to_string(), "123ms");
+        assert_eq!(TimingDisplay(12_000_000_000).to_string(), "12.0s");
+    }
+
+    #[test]
+    fn suppressed_events_output() {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let sampler = LogSampler::new(&crate::settings::LogSampling {
+            max_events: Some(1),
+            interval_ms: 20,
+            ..Default::default()
+        })
+        .unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(LogFmtLayer::new(move || make_writer.clone()).with_sampler(sampler));
+
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..4 {
+                if i == 3 {
+                    std::thread::sleep(std::time::Duration::from_millis(30));
+                }
+                tracing::error!(i, "failed");
+            }
+        });
+
+        let lines = writer.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("level=error msg=failed i=0"));
+        assert!(lines[1].starts_with("level=error msg=\"suppressed 2 events\" suppressed=2"));
+        assert!(lines[2].starts_with("level=error msg=failed i=3"));
+    }
+
+    #[test]
+    fn flushes_suppressed_events_without_later_event() {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let sampler = LogSampler::new(&crate::settings::LogSampling {
+            max_events: Some(1),
+            interval_ms: 20,
+            ..Default::default()
+        })
+        .unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(LogFmtLayer::new(move || make_writer.clone()).with_sampler(sampler));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        for i in 0..3 {
+            tracing::error!(i, "failed");
+        }
+        assert_eq!(writer.lines().len(), 1);
+
+        // The flood ends, and is reported once its interval does.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let lines = writer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("level=error msg=\"suppressed 2 events\" suppressed=2"));
+    }
 
     #[test]
     fn quote_and_escape_len0() {
@@ -0,0 +1,249 @@
+//! Log file writers for [LogFile] settings, rotating files daily, hourly
+//! (through [tracing_appender]'s [RollingFileAppender]) or by size, and
+//! deleting the oldest files past the retention count.
+
+use crate::settings::{LogFile, LogRotation};
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+
+/// File name suffix of log files.
+const SUFFIX: &str = "log";
+
+/// Create a non-blocking writer for `settings`, and its guard, flushing
+/// buffered lines when dropped. Fails if `max_files` is 0.
+pub fn non_blocking(settings: &LogFile) -> Result<(NonBlocking, WorkerGuard)> {
+    if settings.max_files == Some(0) {
+        return Err(anyhow!(
+            "invalid max_files of log files in {}: must be at least 1",
+            settings.directory.display()
+        ));
+    }
+
+    let rotation = match settings.rotation {
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Size => {
+            let writer = SizeRollingWriter::new(settings)?;
+            return Ok(tracing_appender::non_blocking(writer));
+        }
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&settings.prefix)
+        .filename_suffix(SUFFIX);
+    if let Some(max_files) = settings.max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder.build(&settings.directory).map_err(|err| {
+        anyhow!(
+            "failed to open log file in {}: {err}",
+            settings.directory.display()
+        )
+    })?;
+
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+/// Writer appending to `<prefix>.log`, and rotating it to `<prefix>.1.log`
+/// (shifting older files to `<prefix>.2.log` and so on) once it reaches the
+/// maximum size, at the end of a line.
+#[derive(Debug)]
+pub struct SizeRollingWriter {
+    directory: PathBuf,
+    prefix: String,
+    max_size_bytes: u64,
+    max_files: Option<usize>,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingWriter {
+    /// Create a new [SizeRollingWriter], appending to an existing file.
+    pub fn new(settings: &LogFile) -> Result<Self> {
+        fs::create_dir_all(&settings.directory).map_err(|err| {
+            anyhow!(
+                "failed to create log directory {}: {err}",
+                settings.directory.display()
+            )
+        })?;
+        let file = open(&log_path(&settings.directory, &settings.prefix, 0))?;
+
+        Ok(Self {
+            directory: settings.directory.clone(),
+            prefix: settings.prefix.clone(),
+            max_size_bytes: settings.max_size_bytes,
+            max_files: settings.max_files,
+            size: file.metadata()?.len(),
+            file,
+        })
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        log_path(&self.directory, &self.prefix, index)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        // Rotated files retained, besides the current one.
+        let retained = self
+            .max_files
+            .map_or(usize::MAX, |max_files| max_files.saturating_sub(1));
+
+        if retained == 0 {
+            self.file = File::create(self.path(0))?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let mut last = 0;
+        while last < retained && self.path(last + 1).exists() {
+            last += 1;
+        }
+        if last == retained {
+            fs::remove_file(self.path(last))?;
+            last -= 1;
+        }
+        for index in (0..=last).rev() {
+            fs::rename(self.path(index), self.path(index + 1))?;
+        }
+
+        self.file = open(&self.path(0))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        // Lines may be written in several parts, so only rotate at the end
+        // of one.
+        if self.size >= self.max_size_bytes && buf[..written].ends_with(b"\n") {
+            self.rotate()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Path of the current log file (`index` 0), or of a rotated one.
+fn log_path(directory: &Path, prefix: &str, index: usize) -> PathBuf {
+    let name = match index {
+        0 => format!("{prefix}.{SUFFIX}"),
+        index => format!("{prefix}.{index}.{SUFFIX}"),
+    };
+    directory.join(name)
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retains_max_files_with_time_based_rotation() {
+        let directory = std::env::temp_dir().join(format!("log-file-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&directory).unwrap();
+        for hour in 0..3 {
+            fs::write(
+                directory.join(format!("app.2020-01-01-0{hour}.log")),
+                "old\n",
+            )
+            .unwrap();
+            // Ordered by creation time.
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        fs::write(directory.join("other.log"), "other\n").unwrap();
+
+        let settings = LogFile {
+            directory: directory.clone(),
+            prefix: "app".to_string(),
+            rotation: LogRotation::Hourly,
+            max_size_bytes: 10,
+            max_files: Some(2),
+            level: None,
+        };
+        let (mut writer, guard) = non_blocking(&settings).unwrap();
+        writer.write_all(b"new\n").unwrap();
+        drop(guard);
+
+        let mut files: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0], "app.2020-01-01-02.log");
+        assert!(files[1].starts_with("app.") && files[1] != files[0]);
+        assert_eq!(
+            fs::read_to_string(directory.join(&files[1])).unwrap(),
+            "new\n"
+        );
+        assert_eq!(files[2], "other.log");
+
+        // A retention of 0 files is rejected, rather than underflowing.
+        for rotation in [LogRotation::Daily, LogRotation::Hourly, LogRotation::Size] {
+            let settings = LogFile {
+                max_files: Some(0),
+                rotation,
+                ..settings.clone()
+            };
+            assert!(non_blocking(&settings).is_err());
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size_and_retains_max_files() {
+        let directory = std::env::temp_dir().join(format!("log-file-{}", ulid::Ulid::new()));
+        let settings = LogFile {
+            directory: directory.clone(),
+            prefix: "app".to_string(),
+            rotation: LogRotation::Size,
+            max_size_bytes: 10,
+            max_files: Some(3),
+            level: None,
+        };
+
+        let mut writer = SizeRollingWriter::new(&settings).unwrap();
+        for line in 1..=7 {
+            // Not rotated in the middle of a line.
+            writer.write_all(b"line").unwrap();
+            writer.write_all(format!(" {line}\n").as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(read("app.log"), "line 7\n");
+        assert_eq!(read("app.1.log"), "line 5\nline 6\n");
+        assert_eq!(read("app.2.log"), "line 3\nline 4\n");
+        assert!(!directory.join("app.3.log").exists());
+
+        // Appends to the current file when reopened.
+        let mut writer = SizeRollingWriter::new(&settings).unwrap();
+        writer.write_all(b"line").unwrap();
+        assert_eq!(read("app.log"), "line 7\nline");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
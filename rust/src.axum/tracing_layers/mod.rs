@@ -5,5 +5,9 @@
 //! [Composing an observable Rust application]: <https://blog.logrocket.com/composing-underpinnings-observable-rust-application/>
 
 pub mod format_layer;
+pub mod log_file;
+pub mod log_filter;
+pub mod log_sampler;
 pub mod metrics_layer;
+pub mod otel_log_layer;
 pub mod storage_layer;
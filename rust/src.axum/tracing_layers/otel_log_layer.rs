@@ -0,0 +1,250 @@
+//! [Layer] bridging [tracing] events to [Opentelemetry] log records, exported
+//! via the logger pipeline in [tracer](crate::tracer).
+//!
+//! Records carry the trace and span ids of the active span (as recorded by
+//! the [tracing_opentelemetry] layer), along with event fields and the
+//! contextual values kept in [Storage]. With a [StorageReader], they carry
+//! those of the current span even if the layer's filter disables it.
+//!
+//! [Layer]: tracing_subscriber::Layer
+//! [Opentelemetry]: https://opentelemetry.io/
+
+use crate::tracing_layers::storage_layer::{Storage, StorageReader};
+use opentelemetry::{
+    logs::{AnyValue, LogRecord as _, Logger as _, LoggerProvider as _, Severity},
+    trace::{SpanContext, TraceContextExt, TraceState},
+    Key,
+};
+use opentelemetry_sdk::logs::{Logger, LoggerProvider, TraceContext};
+use std::{borrow::Cow, fmt, time::SystemTime};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Fields to skip from [Storage] spans when attaching them to log records.
+const SKIP_STORAGE_FIELDS: [&str; 5] = ["authorization", "error", "msg", "return", "trace_id"];
+
+/// Targets of the OTLP exporters' own dependencies. Their events are never
+/// exported, as exporting them would feed the exporter its own logs, e.g. at
+/// a `debug` level set through the admin server.
+pub const EXPORTER_TARGETS: [&str; 9] = [
+    "h2",
+    "hyper",
+    "hyper_util",
+    "opentelemetry",
+    "opentelemetry_otlp",
+    "opentelemetry_sdk",
+    "reqwest",
+    "tonic",
+    "tower",
+];
+
+/// Whether `target` is one of the [EXPORTER_TARGETS], or of their modules.
+pub fn is_exporter_target(target: &str) -> bool {
+    EXPORTER_TARGETS.iter().any(|exporter_target| {
+        target
+            .strip_prefix(exporter_target)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+/// Logging layer emitting events as OTLP log records.
+#[derive(Debug)]
+pub struct OtelLogLayer {
+    logger: Logger,
+    storage_reader: StorageReader,
+}
+
+impl OtelLogLayer {
+    /// Create a new layer emitting records through a [Logger] obtained from
+    /// `provider`.
+    pub fn new(provider: &LoggerProvider) -> Self {
+        Self {
+            logger: provider
+                .logger_builder(env!("CARGO_PKG_NAME"))
+                .with_version(env!("CARGO_PKG_VERSION"))
+                .build(),
+            storage_reader: StorageReader::default(),
+        }
+    }
+
+    /// Attach the fields of a [StorageReader] stored on the current span, and
+    /// its trace context, to records, even if this layer's filter disables
+    /// the span.
+    pub fn with_storage_reader(self, storage_reader: StorageReader) -> Self {
+        Self {
+            storage_reader,
+            ..self
+        }
+    }
+}
+
+impl<S> Layer<S> for OtelLogLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        let mut record = self.logger.create_log_record();
+        record.set_timestamp(SystemTime::now());
+        record.set_severity_number(severity(metadata.level()));
+        record.set_severity_text(Cow::Borrowed(metadata.level().as_str()));
+        record.add_attribute("target", metadata.target());
+
+        let mut visitor = EventVisitor::default();
+        event.record(&mut visitor);
+        if let Some(body) = visitor.body {
+            record.set_body(body);
+        }
+        record.add_attributes(visitor.attributes);
+
+        let visible_span = ctx.lookup_current();
+        let mut stored_keys = Vec::new();
+        if let Some(current_span) = &visible_span {
+            let extensions = current_span.extensions();
+
+            if let Some(otel_data) = extensions.get::<OtelData>() {
+                record.trace_context = trace_context(otel_data);
+            }
+
+            if let Some(storage) = extensions.get::<Storage<'_>>() {
+                for (key, value) in storage.values() {
+                    stored_keys.push(key.to_string());
+                    if !SKIP_STORAGE_FIELDS.contains(&key.as_ref()) {
+                        record.add_attribute(key.to_string(), value.to_string());
+                    }
+                }
+            }
+        }
+
+        // The current span, if this layer's filter disables it.
+        if let Some(id) = ctx
+            .current_span()
+            .id()
+            .filter(|id| visible_span.as_ref().map_or(true, |span| span.id() != **id))
+        {
+            if record.trace_context.is_none() {
+                record.trace_context = self
+                    .storage_reader
+                    .with_span(id, |span| {
+                        span.extensions().get::<OtelData>().and_then(trace_context)
+                    })
+                    .flatten();
+            }
+
+            for (key, value) in self.storage_reader.fields(id) {
+                if !stored_keys.contains(&key) && !SKIP_STORAGE_FIELDS.contains(&key.as_str()) {
+                    record.add_attribute(key, value);
+                }
+            }
+        }
+
+        self.logger.emit(record);
+    }
+}
+
+/// Trace and span ids of a span as recorded by the opentelemetry layer,
+/// inheriting the trace id from the parent context when not set on the span
+/// itself.
+fn trace_context(otel_data: &OtelData) -> Option<TraceContext> {
+    let parent_span = otel_data.parent_cx.span();
+    let parent_context = parent_span.span_context();
+
+    let trace_id = otel_data
+        .builder
+        .trace_id
+        .unwrap_or_else(|| parent_context.trace_id());
+    let span_id = otel_data.builder.span_id?;
+
+    let span_context = SpanContext::new(
+        trace_id,
+        span_id,
+        parent_context.trace_flags(),
+        false,
+        TraceState::default(),
+    );
+
+    span_context
+        .is_valid()
+        .then(|| TraceContext::from(&span_context))
+}
+
+fn severity(level: &Level) -> Severity {
+    match *level {
+        Level::TRACE => Severity::Trace,
+        Level::DEBUG => Severity::Debug,
+        Level::INFO => Severity::Info,
+        Level::WARN => Severity::Warn,
+        Level::ERROR => Severity::Error,
+    }
+}
+
+/// Collects the `message` field as the record body and all other event fields
+/// as attributes.
+#[derive(Debug, Default)]
+struct EventVisitor {
+    body: Option<AnyValue>,
+    attributes: Vec<(Key, AnyValue)>,
+}
+
+impl EventVisitor {
+    fn record(&mut self, field: &Field, value: AnyValue) {
+        match field.name() {
+            "message" => self.body = Some(value),
+            name if name.starts_with("log.") => (),
+            name => self.attributes.push((Key::new(name), value)),
+        }
+    }
+}
+
+impl Visit for EventVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, AnyValue::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, AnyValue::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.record(field, AnyValue::from(value)),
+            Err(_) => self.record(field, AnyValue::from(value.to_string())),
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, AnyValue::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, AnyValue::from(value.to_string()));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.record(field, AnyValue::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, AnyValue::from(format!("{value:?}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exporter_targets() {
+        assert!(is_exporter_target("tonic"));
+        assert!(is_exporter_target("h2::codec::framed_write"));
+        assert!(is_exporter_target("opentelemetry_sdk::logs"));
+        assert!(!is_exporter_target("hyperloop"));
+        assert!(!is_exporter_target("reqwest_retry::middleware"));
+        assert!(!is_exporter_target("{{crate_name}}::middleware::logging"));
+    }
+}
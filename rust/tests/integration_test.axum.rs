@@ -1,4 +1,5 @@
 use http::Uri;
+use opentelemetry::trace::SpanKind;
 use reqwest::Client;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::policies::ExponentialBackoff;
@@ -6,6 +7,8 @@ use reqwest_tracing::TracingMiddleware;
 use serde::Deserialize;
 use serde_with::serde_as;
 use std::time::Duration;
+use tracing::Instrument;
+use tracing_subscriber::prelude::*;
 use wiremock::{
     matchers::{method, path},
     Mock, MockServer, ResponseTemplate,
@@ -16,6 +19,7 @@ use {{crate_name}}::{
         reqwest_tracing::ExtendedTrace,
     },
     settings::{AppEnvironment, HttpClient, HttpClientRetryOptions, Settings},
+    tracer::{init_tracer_with_exporter, InMemoryExporter},
 };
 
 /// Test loading settings.
@@ -116,3 +120,49 @@ async fn test_client() {
     let res = client.query().await.unwrap();
     assert_eq!(res.status().as_u16(), 200);
 }
+
+/// Test client spans are exported, via an in-memory exporter.
+#[tokio::test]
+async fn test_client_spans() {
+    let mock_server = MockServer::start().await;
+    let settings = Settings::load().unwrap();
+
+    let exporter = InMemoryExporter::new();
+    let tracer =
+        init_tracer_with_exporter(settings.otel(), settings.environment(), exporter.clone());
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let client = AClient::load(ClientSettings {
+        http_client: HttpClient::default(),
+        url: mock_server.uri().parse::<Uri>().unwrap(),
+    })
+    .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/query"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    client
+        .query()
+        .instrument(tracing::info_span!("test_client_spans"))
+        .await
+        .unwrap();
+
+    let spans = exporter.finished_spans();
+    let root = spans
+        .iter()
+        .find(|span| span.name == "test_client_spans")
+        .unwrap();
+    let client_span = spans
+        .iter()
+        .find(|span| span.span_kind == SpanKind::Client)
+        .unwrap();
+    assert_eq!(
+        client_span.span_context.trace_id(),
+        root.span_context.trace_id()
+    );
+}